@@ -58,11 +58,11 @@ func main() {
 
 	// Index movies
 	for _, m := range movies {
-		if err := idx.AddDoc(m); err != nil {
+		if _, err := idx.AddDoc(m); err != nil {
 			log.Fatalf("AddDoc: %v", err)
 		}
 	}
-	if err := idx.Commit(); err != nil {
+	if _, err := idx.Commit(); err != nil {
 		log.Fatalf("Commit: %v", err)
 	}
 	fmt.Printf("✅ Indexed %d movies\n\n", idx.NumDocs())
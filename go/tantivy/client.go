@@ -14,35 +14,202 @@ package tantivy
 import "C"
 import (
 	"encoding/json"
+	"errors"
 	"fmt"
+	"time"
 	"unsafe"
 )
 
+// ErrPanic is wrapped by errors that come from a panic caught inside the native library.
+var ErrPanic = errors.New("native panic")
+
+// ErrorKind categorizes an Error. Values match the native library's stable codes.
+type ErrorKind string
+
+const (
+	KindBadRequest     ErrorKind = "bad_request"     // code 1: invalid query, document or options
+	KindNotFound       ErrorKind = "not_found"       // code 2: missing index, file or handle
+	KindSchemaMismatch ErrorKind = "schema_mismatch" // code 3: unknown field or wrong field type
+	KindIO             ErrorKind = "io"              // code 4: filesystem or storage failure
+	KindLockBusy       ErrorKind = "lock_busy"       // code 5: index writer lock held elsewhere
+	KindInternal       ErrorKind = "internal"        // code 6: bug or unexpected native failure
+	KindPanic          ErrorKind = "panic"           // code 7: panic caught inside the native library
+)
+
+// Error is a structured error returned by the native library.
+// Use errors.As to inspect it, or errors.Is against the Err* kind sentinels.
+type Error struct {
+	Op      string    `json:"-"`
+	Code    int       `json:"code"`
+	Kind    ErrorKind `json:"kind"`
+	Message string    `json:"message"`
+	Path    string    `json:"path,omitempty"` // offending input location, when known
+}
+
+// Kind sentinels for errors.Is.
+var (
+	ErrBadRequest     = &Error{Kind: KindBadRequest}
+	ErrNotFound       = &Error{Kind: KindNotFound}
+	ErrSchemaMismatch = &Error{Kind: KindSchemaMismatch}
+	ErrIO             = &Error{Kind: KindIO}
+	ErrLockBusy       = &Error{Kind: KindLockBusy}
+	ErrInternal       = &Error{Kind: KindInternal}
+)
+
+func (e *Error) Error() string {
+	msg := fmt.Sprintf("tantivy %s: %s", e.Op, e.Message)
+	if e.Path != "" {
+		msg += " (at " + e.Path + ")"
+	}
+	return msg
+}
+
+// Is matches kind sentinels such as ErrNotFound.
+func (e *Error) Is(target error) bool {
+	t, ok := target.(*Error)
+	return ok && t.Message == "" && t.Kind == e.Kind
+}
+
+// Unwrap exposes ErrPanic for errors caused by a caught native panic.
+func (e *Error) Unwrap() error {
+	if e.Kind == KindPanic {
+		return ErrPanic
+	}
+	return nil
+}
+
 // FieldDef defines a field in the schema.
 type FieldDef struct {
 	Name      string `json:"name"`
-	Type      string `json:"type"` // "text", "i64", "f64"
+	Type      string `json:"type"` // "text", "i64", "f64", "bytes" (base64 string in JSON)
 	Stored    bool   `json:"stored"`
 	Indexed   bool   `json:"indexed"`
 	Fast      bool   `json:"fast,omitempty"`
 	Tokenizer string `json:"tokenizer,omitempty"` // "default", "raw", "en_stem"
+	BM25      *BM25  `json:"bm25,omitempty"`      // term scoring parameters; requests can override
+}
+
+// BM25 overrides BM25 scoring parameters. Nil values fall back to the next
+// level: request, schema field, then k1 1.2 and b 0.75.
+type BM25 struct {
+	K1 *float64 `json:"k1,omitempty"` // term frequency saturation
+	B  *float64 `json:"b,omitempty"`  // length normalization, 0 to 1
+}
+
+// BM25Request sets BM25 parameters for one search: for every field, and per
+// field in Fields.
+type BM25Request struct {
+	BM25
+	Fields map[string]BM25 `json:"fields,omitempty"`
 }
 
 // Schema defines the index schema.
 type Schema struct {
 	Fields       []FieldDef `json:"fields"`
-	SearchFields []string   `json:"search_fields,omitempty"` // default text search fields
+	SearchFields []string   `json:"search_fields,omitempty"` // default text search fields; "title^3" weights a field
+}
+
+// Options are runtime settings for opening or creating an index.
+type Options struct {
+	// ReloadPolicy is "on_commit" (default) or "manual". Under "manual", new
+	// commits only become visible to searches after Refresh.
+	ReloadPolicy string `json:"reload_policy,omitempty"`
+
+	// AutoCommit starts background commits as soon as the index opens, as a
+	// later SetAutoCommit would. Nil leaves auto-commit off.
+	AutoCommit *AutoCommitConfig `json:"auto_commit,omitempty"`
+}
+
+// AutoCommitConfig controls background commits. Zero values disable a trigger;
+// with both zero, auto-commit is off.
+type AutoCommitConfig struct {
+	MaxDocs    uint64 `json:"max_docs,omitempty"`    // commit after this many pending writes
+	IntervalMs uint64 `json:"interval_ms,omitempty"` // commit pending writes this often
+}
+
+// SearchRequest wraps a query tree with paging, sorting, projection,
+// highlighting and aggregations. Pass it to Search in place of a flat query.
+// A nil Limit uses the server default of 100; Ptr(0) returns only totals and
+// aggregations.
+type SearchRequest struct {
+	Query          interface{}            `json:"query"`
+	Limit          *int                   `json:"limit,omitempty"`
+	Offset         int                    `json:"offset,omitempty"`
+	Sort           *Sort                  `json:"sort,omitempty"`
+	Fields         []string               `json:"fields,omitempty"`
+	ExcludeFields  []string               `json:"exclude_fields,omitempty"`
+	DocValueFields []string               `json:"docvalue_fields,omitempty"`
+	Highlight      *Highlight             `json:"highlight,omitempty"`
+	Aggs           map[string]interface{} `json:"aggs,omitempty"`
+	Explain        bool                   `json:"explain,omitempty"` // attach "_explanation" to each hit
+	Scoring        string                 `json:"scoring,omitempty"` // "bm25" (default) or "none" for pure filters
+	BM25           *BM25Request           `json:"bm25,omitempty"`
+	Collapse       *Collapse              `json:"collapse,omitempty"`
+	MinScore       *float64               `json:"min_score,omitempty"`  // drop hits scoring below this; nil keeps all
+	CountOnly      bool                   `json:"count_only,omitempty"` // only fill TotalCount
+}
+
+// Ptr returns a pointer to v, for optional fields such as SearchRequest.Limit.
+func Ptr[T any](v T) *T {
+	return &v
+}
+
+// Collapse keeps only the best hit per distinct value of a fast field. Each
+// hit carries its group value in "_group" and, with InnerHits > 0, the
+// group's best hits in "_inner_hits". Counting TotalGroups holds every
+// matching group key in memory, so cost grows with the number of groups.
+type Collapse struct {
+	Field     string `json:"field"`
+	InnerHits int    `json:"inner_hits,omitempty"`
+}
+
+// Sort orders hits by an i64/f64 fast field. Order is "asc" or "desc" (default).
+type Sort struct {
+	Field string `json:"field"`
+	Order string `json:"order,omitempty"`
+}
+
+// Highlight requests HTML snippets for stored text fields, returned in each
+// hit's "_highlight" map.
+type Highlight struct {
+	Fields   []string `json:"fields"`
+	MaxChars int      `json:"max_chars,omitempty"`
 }
 
 // SearchResults is the generic result from a search.
 type SearchResults struct {
-	Results    []map[string]interface{} `json:"results"`
-	Count      int                      `json:"count"`
-	TotalCount int                      `json:"total_count"`
-	Limit      int                      `json:"limit"`
-	Offset     int                      `json:"offset"`
+	Results      []map[string]interface{} `json:"results"`
+	Count        int                      `json:"count"`
+	TotalCount   int                      `json:"total_count"`
+	Limit        int                      `json:"limit"`
+	Offset       int                      `json:"offset"`
+	TotalGroups  int                      `json:"total_groups,omitempty"` // distinct groups, for collapsed searches
+	Aggregations map[string]interface{}   `json:"aggregations,omitempty"`
+	Warnings     []string                 `json:"warnings,omitempty"` // request parts that were ignored or recovered from
+}
+
+// ColumnarResults is a column-oriented search result: one slice per field,
+// aligned by hit position, with nil where a hit lacks the field.
+type ColumnarResults struct {
+	Columns      map[string][]interface{}
+	Count        int
+	TotalCount   int
+	Limit        int
+	Offset       int
+	TotalGroups  int
+	Aggregations map[string]interface{}
+	Warnings     []string
 }
 
+// Encoding selects the wire format used to transfer search results.
+type Encoding uint32
+
+const (
+	EncodingJSON            Encoding = 0 // JSON (default)
+	EncodingMsgpack         Encoding = 1 // MessagePack; integers decode as int64
+	EncodingMsgpackColumnar Encoding = 2 // column-oriented MessagePack, see SearchColumns
+)
+
 // Index is a handle to a Tantivy index.
 type Index struct {
 	handle C.TantivyIndexHandle
@@ -50,19 +217,30 @@ type Index struct {
 
 // Create creates a new index at the given path with the given schema.
 func Create(path string, schema Schema) (*Index, error) {
+	return CreateWithOptions(path, schema, Options{})
+}
+
+// CreateWithOptions creates a new index with the given schema and runtime options.
+func CreateWithOptions(path string, schema Schema, opts Options) (*Index, error) {
 	schemaJSON, err := json.Marshal(schema)
 	if err != nil {
 		return nil, fmt.Errorf("marshal schema: %w", err)
 	}
+	optsJSON, err := json.Marshal(opts)
+	if err != nil {
+		return nil, fmt.Errorf("marshal options: %w", err)
+	}
 
 	cPath := C.CString(path)
 	cSchema := C.CString(string(schemaJSON))
+	cOpts := C.CString(string(optsJSON))
 	defer C.free(unsafe.Pointer(cPath))
 	defer C.free(unsafe.Pointer(cSchema))
+	defer C.free(unsafe.Pointer(cOpts))
 
 	var errOut *C.char
-	h := C.tantivy_create_index(cPath, cSchema, &errOut)
-	if h == nil {
+	h := C.tantivy_create_index_with_options(cPath, cSchema, cOpts, &errOut)
+	if h == 0 {
 		return nil, ffiErr(errOut, "create")
 	}
 	return &Index{handle: h}, nil
@@ -70,59 +248,136 @@ func Create(path string, schema Schema) (*Index, error) {
 
 // Open opens an existing index (schema is read from _schema.json in the index dir).
 func Open(path string) (*Index, error) {
+	return OpenWithOptions(path, Options{})
+}
+
+// OpenWithOptions opens an existing index with the given runtime options.
+func OpenWithOptions(path string, opts Options) (*Index, error) {
+	optsJSON, err := json.Marshal(opts)
+	if err != nil {
+		return nil, fmt.Errorf("marshal options: %w", err)
+	}
+
 	cPath := C.CString(path)
+	cOpts := C.CString(string(optsJSON))
 	defer C.free(unsafe.Pointer(cPath))
+	defer C.free(unsafe.Pointer(cOpts))
 
 	var errOut *C.char
-	h := C.tantivy_open_index(cPath, &errOut)
-	if h == nil {
+	h := C.tantivy_open_index_with_options(cPath, cOpts, &errOut)
+	if h == 0 {
 		return nil, ffiErr(errOut, "open")
 	}
 	return &Index{handle: h}, nil
 }
 
-// Close frees the index handle.
+// Close frees the index handle. Pending WaitForOpstamp calls fail with "index
+// closed"; Close waits for other calls still running on other goroutines to
+// return, after which the index can be reopened.
 func (idx *Index) Close() {
-	if idx.handle != nil {
+	if idx.handle != 0 {
 		C.tantivy_free_index(idx.handle)
-		idx.handle = nil
+		idx.handle = 0
 	}
 }
 
 // AddDoc adds a document (as a map or struct that marshals to JSON).
-func (idx *Index) AddDoc(doc interface{}) error {
+// Returns the opstamp of the add operation.
+func (idx *Index) AddDoc(doc interface{}) (uint64, error) {
 	docJSON, err := json.Marshal(doc)
 	if err != nil {
-		return fmt.Errorf("marshal doc: %w", err)
+		return 0, fmt.Errorf("marshal doc: %w", err)
+	}
+	return idx.AddDocJSON(docJSON)
+}
+
+// AddDocJSON adds a document from raw JSON bytes.
+// Returns the opstamp of the add operation.
+func (idx *Index) AddDocJSON(docJSON []byte) (uint64, error) {
+	var errOut *C.char
+	op := C.tantivy_add_doc_buf(idx.handle, bufPtr(docJSON), C.size_t(len(docJSON)), &errOut)
+	if op < 0 {
+		return 0, ffiErr(errOut, "add_doc")
+	}
+	return uint64(op), nil
+}
+
+// DeleteTerm deletes all documents whose field exactly matches value.
+// Returns the opstamp of the delete operation.
+func (idx *Index) DeleteTerm(field string, value interface{}) (uint64, error) {
+	valueJSON, err := json.Marshal(value)
+	if err != nil {
+		return 0, fmt.Errorf("marshal value: %w", err)
 	}
 
-	cDoc := C.CString(string(docJSON))
-	defer C.free(unsafe.Pointer(cDoc))
+	fieldBytes := []byte(field)
+	var errOut *C.char
+	op := C.tantivy_delete_term_buf(idx.handle, bufPtr(fieldBytes), C.size_t(len(fieldBytes)),
+		bufPtr(valueJSON), C.size_t(len(valueJSON)), &errOut)
+	if op < 0 {
+		return 0, ffiErr(errOut, "delete_term")
+	}
+	return uint64(op), nil
+}
 
+// Commit commits all pending writes to disk. Returns the commit opstamp.
+func (idx *Index) Commit() (uint64, error) {
 	var errOut *C.char
-	if C.tantivy_add_doc(idx.handle, cDoc, &errOut) != 0 {
-		return ffiErr(errOut, "add_doc")
+	op := C.tantivy_commit(idx.handle, &errOut)
+	if op < 0 {
+		return 0, ffiErr(errOut, "commit")
 	}
-	return nil
+	return uint64(op), nil
 }
 
-// AddDocJSON adds a document from raw JSON bytes.
-func (idx *Index) AddDocJSON(docJSON []byte) error {
-	cDoc := C.CString(string(docJSON))
-	defer C.free(unsafe.Pointer(cDoc))
+// SetAutoCommit enables or reconfigures background commits. Pending writes are
+// flushed when auto-commit is reconfigured and when the index is closed.
+func (idx *Index) SetAutoCommit(cfg AutoCommitConfig) error {
+	cfgJSON, err := json.Marshal(cfg)
+	if err != nil {
+		return fmt.Errorf("marshal auto-commit config: %w", err)
+	}
+
+	cCfg := C.CString(string(cfgJSON))
+	defer C.free(unsafe.Pointer(cCfg))
 
 	var errOut *C.char
-	if C.tantivy_add_doc(idx.handle, cDoc, &errOut) != 0 {
-		return ffiErr(errOut, "add_doc")
+	if C.tantivy_set_auto_commit(idx.handle, cCfg, &errOut) != 0 {
+		return ffiErr(errOut, "set_auto_commit")
 	}
 	return nil
 }
 
-// Commit commits all pending writes to disk.
-func (idx *Index) Commit() error {
+// LastError returns the error from the most recent failed background commit, or nil.
+func (idx *Index) LastError() error {
+	errOut := C.tantivy_last_error(idx.handle)
+	if errOut == nil {
+		return nil
+	}
+	return ffiErr(errOut, "auto_commit")
+}
+
+// WaitForOpstamp blocks until searches reflect every operation up to opstamp
+// (as returned by AddDoc, DeleteTerm or Commit). Returns false on timeout, and
+// an error if the index is closed meanwhile. A negative timeout checks once
+// without waiting.
+func (idx *Index) WaitForOpstamp(opstamp uint64, timeout time.Duration) (bool, error) {
+	if timeout < 0 {
+		timeout = 0
+	}
 	var errOut *C.char
-	if C.tantivy_commit(idx.handle, &errOut) != 0 {
-		return ffiErr(errOut, "commit")
+	r := C.tantivy_wait_for_opstamp(idx.handle, C.uint64_t(opstamp), C.uint64_t(timeout.Milliseconds()), &errOut)
+	if r < 0 {
+		return false, ffiErr(errOut, "wait_for_opstamp")
+	}
+	return r == 1, nil
+}
+
+// Refresh reloads the reader so searches see every commit made so far.
+func (idx *Index) Refresh() error {
+	var errOut *C.char
+	if C.tantivy_refresh(idx.handle, &errOut) != 0 {
+		return ffiErr(errOut, "refresh")
 	}
 	return nil
 }
@@ -143,54 +398,332 @@ func (idx *Index) Search(query interface{}) (*SearchResults, error) {
 
 // SearchJSON executes a raw JSON query.
 func (idx *Index) SearchJSON(queryJSON []byte) (*SearchResults, error) {
-	cQuery := C.CString(string(queryJSON))
-	defer C.free(unsafe.Pointer(cQuery))
+	buf, err := idx.searchRaw(0, queryJSON, EncodingJSON)
+	if err != nil {
+		return nil, err
+	}
+	return parseResults(buf, EncodingJSON)
+}
+
+// Validation describes a query that parsed and built successfully.
+type Validation struct {
+	Request  map[string]interface{} `json:"request"` // normalized request, defaults filled in
+	Fields   []string               `json:"fields"`  // schema fields the query refers to
+	Debug    string                 `json:"debug"`   // tantivy query tree
+	Warnings []string               `json:"warnings"`
+}
+
+// ValidateQuery parses and builds a query without running it. On failure the
+// returned *Error's Path points at the offending node, e.g. "query.must[2].field".
+func (idx *Index) ValidateQuery(query interface{}) (*Validation, error) {
+	queryJSON, err := json.Marshal(query)
+	if err != nil {
+		return nil, fmt.Errorf("marshal query: %w", err)
+	}
+
+	var errOut *C.char
+	buf := C.tantivy_validate_query_buf(idx.handle, bufPtr(queryJSON), C.size_t(len(queryJSON)), &errOut)
+	if buf.data == nil {
+		return nil, ffiErr(errOut, "validate_query")
+	}
+	defer C.tantivy_free_buf(buf)
+
+	var v Validation
+	if err := json.Unmarshal(unsafe.Slice((*byte)(unsafe.Pointer(buf.data)), int(buf.len)), &v); err != nil {
+		return nil, fmt.Errorf("parse validation: %w", err)
+	}
+	return &v, nil
+}
+
+// SearchEncoded executes a query, transferring results in the given row-oriented
+// encoding (EncodingJSON or EncodingMsgpack). MessagePack avoids JSON parsing
+// cost on large result pages.
+func (idx *Index) SearchEncoded(query interface{}, enc Encoding) (*SearchResults, error) {
+	return idx.searchEncoded(0, query, enc)
+}
+
+// SearchColumns executes a query and returns column-oriented results,
+// transferred as MessagePack.
+func (idx *Index) SearchColumns(query interface{}) (*ColumnarResults, error) {
+	return idx.searchColumns(0, query)
+}
+
+func (idx *Index) searchEncoded(snap C.TantivySnapshotHandle, query interface{}, enc Encoding) (*SearchResults, error) {
+	if enc == EncodingMsgpackColumnar {
+		return nil, fmt.Errorf("tantivy search: use SearchColumns for columnar results")
+	}
+	buf, err := idx.searchBuf(snap, query, enc)
+	if err != nil {
+		return nil, err
+	}
+	return parseResults(buf, enc)
+}
+
+// msgpackResults decodes row-oriented MessagePack results.
+func msgpackResults(data []byte) (*SearchResults, error) {
+	v, err := decodeMsgpack(data)
+	if err != nil {
+		return nil, fmt.Errorf("parse results: %w", err)
+	}
+	m, _ := v.(map[string]interface{})
+	sr := &SearchResults{
+		Count:       intOf(m["count"]),
+		TotalCount:  intOf(m["total_count"]),
+		Limit:       intOf(m["limit"]),
+		Offset:      intOf(m["offset"]),
+		TotalGroups: intOf(m["total_groups"]),
+	}
+	sr.Aggregations, _ = m["aggregations"].(map[string]interface{})
+	sr.Warnings = stringsOf(m["warnings"])
+	rows, _ := m["results"].([]interface{})
+	sr.Results = make([]map[string]interface{}, 0, len(rows))
+	for _, r := range rows {
+		row, _ := r.(map[string]interface{})
+		sr.Results = append(sr.Results, row)
+	}
+	return sr, nil
+}
+
+func (idx *Index) searchColumns(snap C.TantivySnapshotHandle, query interface{}) (*ColumnarResults, error) {
+	buf, err := idx.searchBuf(snap, query, EncodingMsgpackColumnar)
+	if err != nil {
+		return nil, err
+	}
+	defer C.tantivy_free_buf(buf)
+
+	data := unsafe.Slice((*byte)(unsafe.Pointer(buf.data)), int(buf.len))
+	v, err := decodeMsgpack(data)
+	if err != nil {
+		return nil, fmt.Errorf("parse results: %w", err)
+	}
+	m, _ := v.(map[string]interface{})
+	cr := &ColumnarResults{
+		Columns:     map[string][]interface{}{},
+		Count:       intOf(m["count"]),
+		TotalCount:  intOf(m["total_count"]),
+		Limit:       intOf(m["limit"]),
+		Offset:      intOf(m["offset"]),
+		TotalGroups: intOf(m["total_groups"]),
+	}
+	cr.Aggregations, _ = m["aggregations"].(map[string]interface{})
+	cr.Warnings = stringsOf(m["warnings"])
+	cols, _ := m["columns"].(map[string]interface{})
+	for name, c := range cols {
+		cr.Columns[name], _ = c.([]interface{})
+	}
+	return cr, nil
+}
+
+func (idx *Index) searchBuf(snap C.TantivySnapshotHandle, query interface{}, enc Encoding) (C.TantivyBuf, error) {
+	queryJSON, err := json.Marshal(query)
+	if err != nil {
+		return C.TantivyBuf{}, fmt.Errorf("marshal query: %w", err)
+	}
+	return idx.searchRaw(snap, queryJSON, enc)
+}
+
+// searchRaw runs a search with results in enc.
+func (idx *Index) searchRaw(snap C.TantivySnapshotHandle, queryJSON []byte, enc Encoding) (C.TantivyBuf, error) {
+	var errOut *C.char
+	buf := C.tantivy_search_encoded(idx.handle, snap, bufPtr(queryJSON), C.size_t(len(queryJSON)), C.uint32_t(enc), &errOut)
+	if buf.data == nil {
+		return buf, ffiErr(errOut, "search")
+	}
+	return buf, nil
+}
 
+func stringsOf(v interface{}) []string {
+	items, _ := v.([]interface{})
+	var out []string
+	for _, it := range items {
+		if s, ok := it.(string); ok {
+			out = append(out, s)
+		}
+	}
+	return out
+}
+
+func intOf(v interface{}) int {
+	switch n := v.(type) {
+	case int64:
+		return int(n)
+	case uint64:
+		return int(n)
+	case float64:
+		return int(n)
+	}
+	return 0
+}
+
+// Snapshot pins a searcher generation so that paged reads see consistent
+// results and totals while ingestion continues. Release it when done.
+type Snapshot struct {
+	idx    *Index
+	handle C.TantivySnapshotHandle
+}
+
+// Snapshot acquires a snapshot of the currently visible index state.
+func (idx *Index) Snapshot() (*Snapshot, error) {
 	var errOut *C.char
-	result := C.tantivy_search(idx.handle, cQuery, &errOut)
-	if result == nil {
-		return nil, ffiErr(errOut, "search")
+	h := C.tantivy_acquire_snapshot(idx.handle, &errOut)
+	if h == 0 {
+		return nil, ffiErr(errOut, "snapshot")
+	}
+	return &Snapshot{idx: idx, handle: h}, nil
+}
+
+// Release frees the snapshot handle.
+func (s *Snapshot) Release() {
+	if s.handle != 0 {
+		C.tantivy_release_snapshot(s.handle)
+		s.handle = 0
+	}
+}
+
+// NumDocs returns the number of documents visible in the snapshot.
+func (s *Snapshot) NumDocs() uint64 {
+	return uint64(C.tantivy_snapshot_num_docs(s.handle))
+}
+
+// Search executes a query against the snapshot.
+func (s *Snapshot) Search(query interface{}) (*SearchResults, error) {
+	queryJSON, err := json.Marshal(query)
+	if err != nil {
+		return nil, fmt.Errorf("marshal query: %w", err)
+	}
+	return s.SearchJSON(queryJSON)
+}
+
+// SearchJSON executes a raw JSON query against the snapshot.
+func (s *Snapshot) SearchJSON(queryJSON []byte) (*SearchResults, error) {
+	buf, err := s.idx.searchRaw(s.handle, queryJSON, EncodingJSON)
+	if err != nil {
+		return nil, err
 	}
-	defer C.tantivy_free_string(result)
+	return parseResults(buf, EncodingJSON)
+}
+
+// SearchEncoded is the snapshot variant of Index.SearchEncoded.
+func (s *Snapshot) SearchEncoded(query interface{}, enc Encoding) (*SearchResults, error) {
+	return s.idx.searchEncoded(s.handle, query, enc)
+}
+
+// SearchColumns is the snapshot variant of Index.SearchColumns.
+func (s *Snapshot) SearchColumns(query interface{}) (*ColumnarResults, error) {
+	return s.idx.searchColumns(s.handle, query)
+}
 
-	jsonStr := C.GoString(result)
+// parseResults decodes a native result buffer in place and frees it. The
+// buffer holds results in enc, JSON or row-oriented MessagePack.
+func parseResults(buf C.TantivyBuf, enc Encoding) (*SearchResults, error) {
+	defer C.tantivy_free_buf(buf)
+
+	data := unsafe.Slice((*byte)(unsafe.Pointer(buf.data)), int(buf.len))
+	if enc == EncodingMsgpack {
+		return msgpackResults(data)
+	}
 	var sr SearchResults
-	if err := json.Unmarshal([]byte(jsonStr), &sr); err != nil {
+	if err := json.Unmarshal(data, &sr); err != nil {
 		return nil, fmt.Errorf("parse results: %w", err)
 	}
 	return &sr, nil
 }
 
+// bufPtr passes a Go byte slice to C without copying. The native side only
+// reads it for the duration of the call.
+func bufPtr(b []byte) *C.uint8_t {
+	if len(b) == 0 {
+		return nil
+	}
+	return (*C.uint8_t)(unsafe.Pointer(&b[0]))
+}
+
 // ===== Query Builder Helpers =====
 
+// withLimit sets the limit of the flat request form, for a query passed
+// straight to Search. A limit of 0 leaves it out: nodes nested in another
+// query or used as SearchRequest.Query take 0, since a limit there is ignored
+// and reported in the results' Warnings.
+func withLimit(q map[string]interface{}, limit int) map[string]interface{} {
+	if limit > 0 {
+		q["limit"] = limit
+	}
+	return q
+}
+
 // TextQuery builds a text search query.
 func TextQuery(q string, limit int) map[string]interface{} {
-	return map[string]interface{}{"type": "text", "query": q, "limit": limit}
+	return withLimit(map[string]interface{}{"type": "text", "query": q}, limit)
+}
+
+// TextOptions are query-parser settings for a text query.
+type TextOptions struct {
+	DefaultOperator string               // "or" (default) or "and"
+	Lenient         bool                 // recover from syntax errors, reporting them as warnings
+	Fuzzy           map[string]FuzzyTerm // per-field fuzzy matching
+}
+
+// FuzzyTerm configures fuzzy matching of every term in a field. Nil
+// Distance and Transpositions keep the defaults: distance 1, with a
+// transposition counted as one edit.
+type FuzzyTerm struct {
+	Distance       *int  `json:"distance,omitempty"`
+	Prefix         bool  `json:"prefix,omitempty"`
+	Transpositions *bool `json:"transpositions,omitempty"` // count a transposition as one edit
+}
+
+// TextQueryWithOptions builds a text search query with query-parser options.
+func TextQueryWithOptions(q string, opts TextOptions, limit int) map[string]interface{} {
+	m := TextQuery(q, limit)
+	if opts.DefaultOperator != "" {
+		m["default_operator"] = opts.DefaultOperator
+	}
+	if opts.Lenient {
+		m["lenient"] = true
+	}
+	if len(opts.Fuzzy) > 0 {
+		m["fuzzy"] = opts.Fuzzy
+	}
+	return m
 }
 
 // FuzzyQuery builds a fuzzy search query.
 func FuzzyQuery(term string, distance int, limit int) map[string]interface{} {
-	return map[string]interface{}{"type": "fuzzy", "term": term, "distance": distance, "limit": limit}
+	return withLimit(map[string]interface{}{"type": "fuzzy", "term": term, "distance": distance}, limit)
 }
 
 // PhraseQuery builds a phrase search query.
 func PhraseQuery(phrase string, limit int) map[string]interface{} {
-	return map[string]interface{}{"type": "phrase", "phrase": phrase, "limit": limit}
+	return withLimit(map[string]interface{}{"type": "phrase", "phrase": phrase}, limit)
+}
+
+// ProximityQuery builds a query matching documents where all words of q appear
+// within distance words of each other, in order if ordered is true.
+func ProximityQuery(q string, distance int, ordered bool, limit int) map[string]interface{} {
+	return withLimit(map[string]interface{}{
+		"type": "proximity", "query": q, "distance": distance, "ordered": ordered,
+	}, limit)
 }
 
 // PrefixQuery builds a prefix search query.
 func PrefixQuery(prefix string, limit int) map[string]interface{} {
-	return map[string]interface{}{"type": "prefix", "prefix": prefix, "limit": limit}
+	return withLimit(map[string]interface{}{"type": "prefix", "prefix": prefix}, limit)
 }
 
 // TermMatchQuery builds an exact term match query.
 func TermMatchQuery(field string, value interface{}, limit int) map[string]interface{} {
-	return map[string]interface{}{"type": "term_match", "field": field, "value": value, "limit": limit}
+	return withLimit(map[string]interface{}{"type": "term_match", "field": field, "value": value}, limit)
+}
+
+// TermsQuery builds a query matching field against any of values (a slice of
+// strings, ints or floats). Suited to large ID filters.
+func TermsQuery(field string, values interface{}, limit int) map[string]interface{} {
+	return withLimit(map[string]interface{}{"type": "terms", "field": field, "values": values}, limit)
 }
 
 // RangeI64Query builds an integer range query.
 func RangeI64Query(field string, min, max *int64, limit int) map[string]interface{} {
-	q := map[string]interface{}{"type": "range_i64", "field": field, "limit": limit}
+	q := withLimit(map[string]interface{}{"type": "range_i64", "field": field}, limit)
 	if min != nil {
 		q["min"] = *min
 	}
@@ -202,7 +735,7 @@ func RangeI64Query(field string, min, max *int64, limit int) map[string]interfac
 
 // RangeF64Query builds a float range query.
 func RangeF64Query(field string, min, max *float64, limit int) map[string]interface{} {
-	q := map[string]interface{}{"type": "range_f64", "field": field, "limit": limit}
+	q := withLimit(map[string]interface{}{"type": "range_f64", "field": field}, limit)
 	if min != nil {
 		q["min"] = *min
 	}
@@ -223,16 +756,162 @@ func BoolQuery(must, should, mustNot []map[string]interface{}, limit int) map[st
 	if mustNot == nil {
 		mustNot = []map[string]interface{}{}
 	}
-	return map[string]interface{}{
-		"type": "bool", "must": must, "should": should, "must_not": mustNot, "limit": limit,
+	return withLimit(map[string]interface{}{
+		"type": "bool", "must": must, "should": should, "must_not": mustNot,
+	}, limit)
+}
+
+// RegexQuery builds a query matching terms against a regular expression.
+func RegexQuery(pattern string, fields []string, limit int) map[string]interface{} {
+	return withLimit(map[string]interface{}{"type": "regex", "pattern": pattern, "fields": fields}, limit)
+}
+
+// WildcardQuery builds a query matching terms against a glob (* and ?).
+func WildcardQuery(pattern string, fields []string, limit int) map[string]interface{} {
+	return withLimit(map[string]interface{}{"type": "wildcard", "pattern": pattern, "fields": fields}, limit)
+}
+
+// ExistsQuery matches documents that have a value in field.
+func ExistsQuery(field string, limit int) map[string]interface{} {
+	return withLimit(map[string]interface{}{"type": "exists", "field": field}, limit)
+}
+
+// MissingQuery matches documents that have no value in field.
+func MissingQuery(field string, limit int) map[string]interface{} {
+	exists := map[string]interface{}{"type": "exists", "field": field}
+	return BoolQuery(nil, nil, []map[string]interface{}{exists}, limit)
+}
+
+// MoreLikeThisQuery matches documents similar to the one whose "id" field
+// holds id, judged by fields (default: the schema's search fields). Only its
+// stored fields are read, and weights such as "title^2" are ignored with a
+// warning.
+// The source document itself is not returned.
+func MoreLikeThisQuery(id interface{}, fields []string, limit int) map[string]interface{} {
+	return withLimit(map[string]interface{}{"type": "more_like_this", "like_doc": id, "fields": fields}, limit)
+}
+
+// MoreLikeThisTextQuery matches documents similar to text.
+func MoreLikeThisTextQuery(text string, fields []string, limit int) map[string]interface{} {
+	return withLimit(map[string]interface{}{"type": "more_like_this", "like_text": text, "fields": fields}, limit)
+}
+
+// MinimumShouldMatch sets how many should clauses of a bool query must match:
+// an int, a percentage such as "75%", or a negative "all but" form.
+func MinimumShouldMatch(q map[string]interface{}, min interface{}) map[string]interface{} {
+	q["minimum_should_match"] = min
+	return q
+}
+
+// DisMaxQuery builds a query scored by its best-matching sub-query, plus
+// tieBreaker (0 to 1) times the scores of the other matching sub-queries.
+func DisMaxQuery(queries []map[string]interface{}, tieBreaker float64, limit int) map[string]interface{} {
+	return withLimit(map[string]interface{}{
+		"type": "dis_max", "queries": queries, "tie_breaker": tieBreaker,
+	}, limit)
+}
+
+// Boost multiplies the score of a query node.
+func Boost(q map[string]interface{}, boost float64) map[string]interface{} {
+	q["boost"] = boost
+	return q
+}
+
+// ConstantScoreQuery matches what q matches, with every hit scoring score.
+func ConstantScoreQuery(q map[string]interface{}, score float64, limit int) map[string]interface{} {
+	return withLimit(map[string]interface{}{
+		"type": "constant_score", "query": q, "score": score,
+	}, limit)
+}
+
+// ScoreFunction is one function of a function_score query. Set exactly one
+// of FieldValueFactor, Gauss, Exp and Linear. Weight 0 means 1.
+type ScoreFunction struct {
+	FieldValueFactor *FieldValueFactor `json:"field_value_factor,omitempty"`
+	Gauss            *Decay            `json:"gauss,omitempty"`
+	Exp              *Decay            `json:"exp,omitempty"`
+	Linear           *Decay            `json:"linear,omitempty"`
+	Weight           float64           `json:"weight,omitempty"`
+}
+
+// FieldValueFactor scores modifier(factor * value) of an i64/f64 fast field.
+type FieldValueFactor struct {
+	Field    string   `json:"field"`
+	Factor   float64  `json:"factor,omitempty"`   // 0 means 1
+	Modifier string   `json:"modifier,omitempty"` // "log1p", "sqrt", ...; default "none"
+	Missing  *float64 `json:"missing,omitempty"`  // value for documents without one
+}
+
+// Decay scores 1 within Offset of Origin, falling to Decay (default 0.5) at
+// Offset+Scale.
+type Decay struct {
+	Field  string  `json:"field"`
+	Origin float64 `json:"origin"`
+	Scale  float64 `json:"scale"`
+	Offset float64 `json:"offset,omitempty"`
+	Decay  float64 `json:"decay,omitempty"`
+}
+
+// FunctionScoreQuery blends q's score with functions over fast fields. It
+// must be the root query of a search and can't be combined with Sort.
+// scoreMode combines the functions ("multiply", "sum", "avg", "max", "min")
+// and boostMode combines the result with q's score ("multiply", "sum",
+// "replace"); empty strings mean "multiply".
+func FunctionScoreQuery(q map[string]interface{}, functions []ScoreFunction, scoreMode, boostMode string, limit int) map[string]interface{} {
+	m := withLimit(map[string]interface{}{
+		"type": "function_score", "query": q, "functions": functions,
+	}, limit)
+	if scoreMode != "" {
+		m["score_mode"] = scoreMode
+	}
+	if boostMode != "" {
+		m["boost_mode"] = boostMode
 	}
+	return m
+}
+
+// WithFields restricts the stored fields returned for each hit.
+func WithFields(q map[string]interface{}, fields ...string) map[string]interface{} {
+	q["return_fields"] = fields
+	return q
+}
+
+// WithoutFields leaves the given stored fields out of each hit.
+func WithoutFields(q map[string]interface{}, fields ...string) map[string]interface{} {
+	q["exclude_fields"] = fields
+	return q
+}
+
+// WithDocValues returns the given fast fields, read from columnar storage.
+// Without WithFields, stored fields are not loaded at all.
+func WithDocValues(q map[string]interface{}, fields ...string) map[string]interface{} {
+	q["docvalue_fields"] = fields
+	return q
+}
+
+// WithMinScore drops hits scoring below min.
+func WithMinScore(q map[string]interface{}, min float64) map[string]interface{} {
+	q["min_score"] = min
+	return q
+}
+
+// CountOnly makes a search return only TotalCount, skipping hits and doc loading.
+// The search fails if it also sets Aggs, Collapse, Highlight or Explain.
+func CountOnly(q map[string]interface{}) map[string]interface{} {
+	q["count_only"] = true
+	return q
 }
 
 func ffiErr(errOut *C.char, context string) error {
-	if errOut != nil {
-		msg := C.GoString(errOut)
-		C.tantivy_free_string(errOut)
-		return fmt.Errorf("tantivy %s: %s", context, msg)
+	if errOut == nil {
+		return &Error{Op: context, Code: 6, Kind: KindInternal, Message: "unknown error"}
+	}
+	msg := C.GoString(errOut)
+	C.tantivy_free_string(errOut)
+
+	e := &Error{Op: context}
+	if err := json.Unmarshal([]byte(msg), e); err != nil {
+		e.Code, e.Kind, e.Message = 6, KindInternal, msg
 	}
-	return fmt.Errorf("tantivy %s: unknown error", context)
+	return e
 }
@@ -0,0 +1,170 @@
+package tantivy
+
+import (
+	"errors"
+	"fmt"
+	"math"
+)
+
+// decodeMsgpack decodes the MessagePack subset produced by the native library
+// into the same generic shapes encoding/json uses (map[string]interface{},
+// []interface{}, string, bool, nil). Integers decode as int64 (uint64 above
+// math.MaxInt64), floats as float64 and bin as []byte.
+func decodeMsgpack(b []byte) (interface{}, error) {
+	d := &msgpackDecoder{b: b}
+	v, err := d.value()
+	if err == nil && d.off != len(b) {
+		err = fmt.Errorf("msgpack: %d trailing bytes", len(b)-d.off)
+	}
+	return v, err
+}
+
+var errMsgpackShort = errors.New("msgpack: unexpected end of input")
+
+type msgpackDecoder struct {
+	b   []byte
+	off int
+}
+
+func (d *msgpackDecoder) next(n int) ([]byte, error) {
+	if n < 0 || len(d.b)-d.off < n {
+		return nil, errMsgpackShort
+	}
+	s := d.b[d.off : d.off+n]
+	d.off += n
+	return s, nil
+}
+
+// uint reads an n-byte big-endian unsigned integer.
+func (d *msgpackDecoder) uint(n int) (uint64, error) {
+	s, err := d.next(n)
+	if err != nil {
+		return 0, err
+	}
+	var v uint64
+	for _, c := range s {
+		v = v<<8 | uint64(c)
+	}
+	return v, nil
+}
+
+func (d *msgpackDecoder) value() (interface{}, error) {
+	tb, err := d.next(1)
+	if err != nil {
+		return nil, err
+	}
+	t := tb[0]
+	switch {
+	case t <= 0x7f:
+		return int64(t), nil
+	case t >= 0xe0:
+		return int64(int8(t)), nil
+	case t&0xf0 == 0x80:
+		return d.mapN(int(t & 0x0f))
+	case t&0xf0 == 0x90:
+		return d.arrayN(int(t & 0x0f))
+	case t&0xe0 == 0xa0:
+		return d.str(int(t & 0x1f))
+	}
+
+	switch t {
+	case 0xc0:
+		return nil, nil
+	case 0xc2:
+		return false, nil
+	case 0xc3:
+		return true, nil
+	case 0xc4, 0xc5, 0xc6: // bin 8/16/32
+		n, err := d.uint(1 << (t - 0xc4))
+		if err != nil {
+			return nil, err
+		}
+		s, err := d.next(int(n))
+		if err != nil {
+			return nil, err
+		}
+		return append([]byte(nil), s...), nil
+	case 0xca:
+		u, err := d.uint(4)
+		return float64(math.Float32frombits(uint32(u))), err
+	case 0xcb:
+		u, err := d.uint(8)
+		return math.Float64frombits(u), err
+	case 0xcc, 0xcd, 0xce, 0xcf: // uint 8/16/32/64
+		u, err := d.uint(1 << (t - 0xcc))
+		if u > math.MaxInt64 {
+			return u, err
+		}
+		return int64(u), err
+	case 0xd0, 0xd1, 0xd2, 0xd3: // int 8/16/32/64
+		n := 1 << (t - 0xd0)
+		u, err := d.uint(n)
+		shift := 64 - 8*n
+		return int64(u<<shift) >> shift, err
+	case 0xd9, 0xda, 0xdb: // str 8/16/32
+		n, err := d.uint(1 << (t - 0xd9))
+		if err != nil {
+			return nil, err
+		}
+		return d.str(int(n))
+	case 0xdc, 0xdd: // array 16/32
+		n, err := d.uint(2 << (t - 0xdc))
+		if err != nil {
+			return nil, err
+		}
+		return d.arrayN(int(n))
+	case 0xde, 0xdf: // map 16/32
+		n, err := d.uint(2 << (t - 0xde))
+		if err != nil {
+			return nil, err
+		}
+		return d.mapN(int(n))
+	}
+	return nil, fmt.Errorf("msgpack: unsupported type byte 0x%02x", t)
+}
+
+func (d *msgpackDecoder) str(n int) (interface{}, error) {
+	s, err := d.next(n)
+	if err != nil {
+		return nil, err
+	}
+	return string(s), nil
+}
+
+func (d *msgpackDecoder) arrayN(n int) (interface{}, error) {
+	if n > len(d.b)-d.off {
+		return nil, errMsgpackShort
+	}
+	a := make([]interface{}, n)
+	for i := range a {
+		v, err := d.value()
+		if err != nil {
+			return nil, err
+		}
+		a[i] = v
+	}
+	return a, nil
+}
+
+func (d *msgpackDecoder) mapN(n int) (interface{}, error) {
+	if n > len(d.b)-d.off {
+		return nil, errMsgpackShort
+	}
+	m := make(map[string]interface{}, n)
+	for i := 0; i < n; i++ {
+		k, err := d.value()
+		if err != nil {
+			return nil, err
+		}
+		v, err := d.value()
+		if err != nil {
+			return nil, err
+		}
+		ks, ok := k.(string)
+		if !ok {
+			ks = fmt.Sprint(k)
+		}
+		m[ks] = v
+	}
+	return m, nil
+}
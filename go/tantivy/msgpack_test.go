@@ -0,0 +1,129 @@
+package tantivy
+
+import (
+	"encoding/hex"
+	"fmt"
+	"math"
+	"reflect"
+	"strings"
+	"testing"
+)
+
+// Fixtures are the bytes rmp_serde::to_vec_named produces for the value shown,
+// as the native library encodes results. The encoder only emits the 32-bit
+// forms above 65535 entries or bytes, so those fixtures are built by repeating
+// an entry rather than written out.
+var msgpackCases = []struct {
+	name string
+	hex  string
+	want interface{}
+}{
+	{"fixmap", "82a16101a162c0", map[string]interface{}{"a": int64(1), "b": nil}},
+	{"map16", "de0010" + seqHex(16, func(i int) string { return fmt.Sprintf("a36b3%d3%d%02x", i/10, i%10, i) }), seqMap(16)},
+	{"map32", "df00010000" + seqHex(65536, func(i int) string { return fmt.Sprintf("a5%x%02x", fmt.Sprintf("%05d", i), i%128) }), seq32Map()},
+	{"fixstr", "a161", "a"},
+	{"str8", "d928" + strings.Repeat("78", 40), strings.Repeat("x", 40)},
+	{"str16", "da012c" + strings.Repeat("79", 300), strings.Repeat("y", 300)},
+	{"str32", "db00010000" + strings.Repeat("7a", 65536), strings.Repeat("z", 65536)},
+	{"positive fixint", "7f", int64(127)},
+	{"negative fixint", "fb", int64(-5)},
+	{"int8", "d09c", int64(-100)},
+	{"int64", "d3ffffff0000000000", int64(-1099511627776)},
+	{"uint32", "ceee6b2800", int64(4000000000)},
+	{"uint64", "cfffffffffffffffff", uint64(math.MaxUint64)},
+	{"float32", "ca3fc00000", float64(1.5)},
+	{"float64", "cb3fb999999999999a", 0.1},
+	{"nil", "c0", nil},
+	{"true", "c3", true},
+	{"nested arrays", "93920102909103", []interface{}{
+		[]interface{}{int64(1), int64(2)}, []interface{}{}, []interface{}{int64(3)},
+	}},
+	{"search results", "85a7726573756c74739183a65f73636f7265ca3fa00000a26964a161a16ef9" +
+		"a5636f756e7401ab746f74616c5f636f756e7403a56c696d69740aa66f666673657400",
+		map[string]interface{}{
+			"results":     []interface{}{map[string]interface{}{"_score": 1.25, "id": "a", "n": int64(-7)}},
+			"count":       int64(1),
+			"total_count": int64(3),
+			"limit":       int64(10),
+			"offset":      int64(0),
+		}},
+}
+
+// seqHex concatenates item(i) for i in [0, n).
+func seqHex(n int, item func(i int) string) string {
+	var b strings.Builder
+	for i := 0; i < n; i++ {
+		b.WriteString(item(i))
+	}
+	return b.String()
+}
+
+// seqMap is {"k00": 0, "k01": 1, ...} with n entries.
+func seqMap(n int) map[string]interface{} {
+	m := make(map[string]interface{}, n)
+	for i := 0; i < n; i++ {
+		m[fmt.Sprintf("k%02d", i)] = int64(i)
+	}
+	return m
+}
+
+// seq32Map is {"00000": 0, "00001": 1, ...}, values cycling below 128.
+func seq32Map() map[string]interface{} {
+	m := make(map[string]interface{}, 65536)
+	for i := 0; i < 65536; i++ {
+		m[fmt.Sprintf("%05d", i)] = int64(i % 128)
+	}
+	return m
+}
+
+func mustHex(t *testing.T, s string) []byte {
+	t.Helper()
+	b, err := hex.DecodeString(s)
+	if err != nil {
+		t.Fatalf("bad fixture: %v", err)
+	}
+	return b
+}
+
+func TestDecodeMsgpack(t *testing.T) {
+	for _, tc := range msgpackCases {
+		t.Run(tc.name, func(t *testing.T) {
+			got, err := decodeMsgpack(mustHex(t, tc.hex))
+			if err != nil {
+				t.Fatalf("decode: %v", err)
+			}
+			if !reflect.DeepEqual(got, tc.want) {
+				t.Fatalf("got %#v, want %#v", got, tc.want)
+			}
+		})
+	}
+}
+
+func TestDecodeMsgpackTruncated(t *testing.T) {
+	for _, tc := range msgpackCases {
+		if len(tc.hex) > 4096 {
+			continue // the 32-bit fixtures would take a while byte by byte
+		}
+		b := mustHex(t, tc.hex)
+		for n := 0; n < len(b); n++ {
+			if _, err := decodeMsgpack(b[:n]); err == nil {
+				t.Errorf("%s: %d of %d bytes decoded without error", tc.name, n, len(b))
+			}
+		}
+	}
+}
+
+func TestDecodeMsgpackMalformed(t *testing.T) {
+	for _, tc := range []struct{ name, hex string }{
+		{"map32 count past end", "dfffffffff"},
+		{"array32 count past end", "ddffffffff"},
+		{"str32 length past end", "dbffffffff61"},
+		{"bin32 length past end", "c6ffffffff"},
+		{"trailing bytes", "c0c0"},
+		{"unsupported type", "c1"},
+	} {
+		if _, err := decodeMsgpack(mustHex(t, tc.hex)); err == nil {
+			t.Errorf("%s: decoded without error", tc.name)
+		}
+	}
+}
@@ -0,0 +1,486 @@
+//! Field collapsing: the best hits for each distinct value of a fast field.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
+use tantivy::collector::{Collector, SegmentCollector};
+use tantivy::{DocAddress, DocId, Score, SegmentOrdinal, SegmentReader, TantivyError};
+
+use crate::{DocValueColumn, FieldDef, FunctionScorer, HitValue, B64};
+use base64::Engine;
+
+/// The value hits are grouped by. Documents without one form a single group.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum GroupKey {
+    Missing,
+    I64(i64),
+    F64(u64),
+    Str(String),
+    Bytes(Vec<u8>),
+}
+
+impl GroupKey {
+    /// The key as returned in a hit's `_group`.
+    pub(crate) fn value(&self) -> HitValue {
+        match self {
+            GroupKey::Missing => HitValue::Null,
+            GroupKey::I64(n) => HitValue::I64(*n),
+            GroupKey::F64(bits) => HitValue::F64(f64::from_bits(*bits)),
+            GroupKey::Str(s) => HitValue::Str(s.clone()),
+            GroupKey::Bytes(b) => HitValue::Str(B64.encode(b)),
+        }
+    }
+}
+
+/// How a hit ranks within a collapse; higher is better. Sort values are
+/// flipped for ascending order (`!v` for i64, so they stay exact).
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Rank {
+    /// No sort value: last either way.
+    Missing,
+    I64(i64),
+    F64(f64),
+}
+
+impl Ord for Rank {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Rank::Missing, Rank::Missing) => Ordering::Equal,
+            (Rank::Missing, _) => Ordering::Less,
+            (_, Rank::Missing) => Ordering::Greater,
+            (Rank::I64(a), Rank::I64(b)) => a.cmp(b),
+            (Rank::F64(a), Rank::F64(b)) => a.total_cmp(b),
+            (Rank::I64(_), Rank::F64(_)) => Ordering::Less,
+            (Rank::F64(_), Rank::I64(_)) => Ordering::Greater,
+        }
+    }
+}
+
+impl PartialOrd for Rank {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+
+impl PartialEq for Rank {
+    fn eq(&self, other: &Self) -> bool { self.cmp(other) == Ordering::Equal }
+}
+
+impl Eq for Rank {}
+
+impl Rank {
+    /// The score or sort value as returned in a hit; `desc` is false for an
+    /// ascending sort.
+    pub(crate) fn value(self, desc: bool) -> HitValue {
+        match self {
+            Rank::Missing => HitValue::Null,
+            Rank::I64(v) => HitValue::I64(if desc { v } else { !v }),
+            Rank::F64(v) => HitValue::F64(if desc { v } else { -v }),
+        }
+    }
+
+    /// The rank as a hit's `_score`, when hits are ranked by score.
+    pub(crate) fn score(self) -> HitValue {
+        match self {
+            Rank::F64(v) => HitValue::F32(v as Score),
+            rank => rank.value(true),
+        }
+    }
+}
+
+/// What to group by and how to rank hits: by score (after `functions`, if
+/// any) or by a fast field.
+#[derive(Clone)]
+pub(crate) struct GroupSpec {
+    pub(crate) field: (String, FieldDef),
+    /// Sort field and whether larger values rank first.
+    pub(crate) sort: Option<(String, FieldDef, bool)>,
+    pub(crate) functions: Option<Arc<FunctionScorer>>,
+}
+
+fn open(reader: &SegmentReader, name: &str, fd: &FieldDef) -> tantivy::Result<DocValueColumn> {
+    DocValueColumn::open(reader.fast_fields(), name, fd).map_err(|e| TantivyError::SchemaError(e.message))
+}
+
+impl GroupSpec {
+    fn segment(&self, reader: &SegmentReader) -> tantivy::Result<SegmentGroups> {
+        let sort = match &self.sort {
+            Some((name, fd, desc)) => Some((open(reader, name, fd)?, *desc)),
+            None => None,
+        };
+        Ok(SegmentGroups {
+            group: open(reader, &self.field.0, &self.field.1)?,
+            sort,
+            functions: self.functions.as_ref().map(|fs| (fs.clone(), fs.segment(reader))),
+        })
+    }
+}
+
+/// A segment's group and rank columns. Groups are keyed by term ordinal or
+/// value bits, `None` for documents without a value.
+struct SegmentGroups {
+    group: DocValueColumn,
+    sort: Option<(DocValueColumn, bool)>,
+    functions: Option<(Arc<FunctionScorer>, Arc<Vec<DocValueColumn>>)>,
+}
+
+impl SegmentGroups {
+    fn rank(&self, doc: DocId, score: Score) -> Rank {
+        match &self.sort {
+            Some((DocValueColumn::I64(c), desc)) => c.first(doc).map_or(Rank::Missing, |v| Rank::I64(if *desc { v } else { !v })),
+            Some((col, desc)) => col.number(doc).map_or(Rank::Missing, |v| Rank::F64(if *desc { v } else { -v })),
+            None => match &self.functions {
+                Some((fs, cols)) => Rank::F64(fs.score(score, cols, doc) as f64),
+                None => Rank::F64(score as f64),
+            },
+        }
+    }
+
+    fn key(&self, doc: DocId) -> Option<u64> {
+        match &self.group {
+            DocValueColumn::I64(c) => c.first(doc).map(|v| v as u64),
+            DocValueColumn::F64(c) => c.first(doc).map(f64::to_bits),
+            DocValueColumn::Str(Some(c)) => c.term_ords(doc).next(),
+            DocValueColumn::Bytes(Some(c)) => c.term_ords(doc).next(),
+            DocValueColumn::Str(None) | DocValueColumn::Bytes(None) => None,
+        }
+    }
+
+    fn group_key(&self, key: Option<u64>) -> tantivy::Result<GroupKey> {
+        Ok(match (key, &self.group) {
+            (None, _) => GroupKey::Missing,
+            (Some(v), DocValueColumn::I64(_)) => GroupKey::I64(v as i64),
+            (Some(v), DocValueColumn::F64(_)) => GroupKey::F64(v),
+            (Some(ord), DocValueColumn::Str(Some(c))) => {
+                let mut s = String::new();
+                c.ord_to_str(ord, &mut s)?;
+                GroupKey::Str(s)
+            }
+            (Some(ord), DocValueColumn::Bytes(Some(c))) => {
+                let mut buf = Vec::new();
+                c.ord_to_bytes(ord, &mut buf)?;
+                GroupKey::Bytes(buf)
+            }
+            (Some(_), _) => GroupKey::Missing,
+        })
+    }
+
+    /// The segment's key for `group`, or `None` if no document here has it.
+    fn segment_key(&self, group: &GroupKey) -> tantivy::Result<Option<Option<u64>>> {
+        Ok(match (group, &self.group) {
+            (GroupKey::Missing, _) => Some(None),
+            (GroupKey::I64(v), DocValueColumn::I64(_)) => Some(Some(*v as u64)),
+            (GroupKey::F64(bits), DocValueColumn::F64(_)) => Some(Some(*bits)),
+            (GroupKey::Str(s), DocValueColumn::Str(Some(c))) => c.dictionary().term_ord(s.as_bytes())?.map(Some),
+            (GroupKey::Bytes(b), DocValueColumn::Bytes(Some(c))) => c.dictionary().term_ord(b)?.map(Some),
+            _ => None,
+        })
+    }
+}
+
+/// Groups ranked by their best hit, best first, and how many groups matched.
+pub(crate) struct Collapsed {
+    pub(crate) groups: Vec<(GroupKey, Rank, DocAddress)>,
+    pub(crate) total_groups: usize,
+}
+
+/// First pass: the best hit of the `top` best groups. Each segment keeps hits
+/// for at most `top` groups, evicting the worst, so the hits held are bounded
+/// by the page. Counting `total_groups` still keeps every distinct group key,
+/// so memory grows with the number of matching groups.
+pub(crate) struct CollapseCollector {
+    pub(crate) spec: GroupSpec,
+    pub(crate) top: usize,
+}
+
+/// Segment fruit: the kept groups and every group key seen.
+pub(crate) struct SegmentCollapsed {
+    groups: Vec<(GroupKey, Rank, DocAddress)>,
+    keys: HashSet<GroupKey>,
+}
+
+impl Collector for CollapseCollector {
+    type Fruit = Collapsed;
+    type Child = CollapseSegmentCollector;
+
+    fn for_segment(&self, segment_ord: SegmentOrdinal, reader: &SegmentReader) -> tantivy::Result<Self::Child> {
+        Ok(CollapseSegmentCollector {
+            segment_ord,
+            groups: self.spec.segment(reader)?,
+            top: self.top,
+            best: HashMap::new(),
+            order: BTreeSet::new(),
+            seen: HashSet::new(),
+        })
+    }
+
+    fn requires_scoring(&self) -> bool { self.spec.sort.is_none() }
+
+    fn merge_fruits(&self, fruits: Vec<tantivy::Result<SegmentCollapsed>>) -> tantivy::Result<Collapsed> {
+        let mut best: HashMap<GroupKey, (Rank, DocAddress)> = HashMap::new();
+        let mut keys = HashSet::new();
+        for fruit in fruits {
+            let fruit = fruit?;
+            keys.extend(fruit.keys);
+            for (key, rank, addr) in fruit.groups {
+                let entry = best.entry(key).or_insert((rank, addr));
+                if better((rank, addr), *entry) { *entry = (rank, addr); }
+            }
+        }
+        let mut groups: Vec<_> = best.into_iter().map(|(key, (rank, addr))| (key, rank, addr)).collect();
+        // Best group first; ties go to the earlier document.
+        groups.sort_by(|a, b| b.1.cmp(&a.1).then(a.2.cmp(&b.2)));
+        groups.truncate(self.top);
+        Ok(Collapsed { groups, total_groups: keys.len() })
+    }
+}
+
+/// Whether `a` outranks `b`; ties go to the earlier document.
+fn better<D: Ord>(a: (Rank, D), b: (Rank, D)) -> bool {
+    a.0 > b.0 || (a.0 == b.0 && a.1 < b.1)
+}
+
+pub(crate) struct CollapseSegmentCollector {
+    segment_ord: SegmentOrdinal,
+    groups: SegmentGroups,
+    top: usize,
+    best: HashMap<Option<u64>, (Rank, DocId)>,
+    // Kept groups, worst first.
+    order: BTreeSet<(Rank, Reverse<DocId>, Option<u64>)>,
+    seen: HashSet<Option<u64>>,
+}
+
+impl SegmentCollector for CollapseSegmentCollector {
+    type Fruit = tantivy::Result<SegmentCollapsed>;
+
+    fn collect(&mut self, doc: DocId, score: Score) {
+        let key = self.groups.key(doc);
+        self.seen.insert(key);
+        if self.top == 0 { return; }
+        let rank = self.groups.rank(doc, score);
+        // Documents arrive in order, so an equal rank never displaces a hit.
+        if let Some(&(r, d)) = self.best.get(&key) {
+            if rank <= r { return; }
+            self.order.remove(&(r, Reverse(d), key));
+        } else if self.best.len() == self.top {
+            let Some(&(r, _, _)) = self.order.first() else { return };
+            if rank <= r { return; }
+            if let Some((_, _, evicted)) = self.order.pop_first() { self.best.remove(&evicted); }
+        }
+        self.best.insert(key, (rank, doc));
+        self.order.insert((rank, Reverse(doc), key));
+    }
+
+    fn harvest(self) -> tantivy::Result<SegmentCollapsed> {
+        let groups = self.best.into_iter()
+            .map(|(key, (rank, doc))| Ok((self.groups.group_key(key)?, rank, DocAddress::new(self.segment_ord, doc))))
+            .collect::<tantivy::Result<_>>()?;
+        let keys = self.seen.into_iter().map(|key| self.groups.group_key(key)).collect::<tantivy::Result<_>>()?;
+        Ok(SegmentCollapsed { groups, keys })
+    }
+}
+
+/// Top hits of each group, best first.
+pub(crate) type InnerHits = HashMap<GroupKey, Vec<(Rank, DocAddress)>>;
+
+/// Second pass: the `size` best hits of each of `groups`, the groups on the
+/// page a `CollapseCollector` picked.
+pub(crate) struct InnerHitsCollector {
+    pub(crate) spec: GroupSpec,
+    pub(crate) size: usize,
+    pub(crate) groups: Arc<Vec<GroupKey>>,
+}
+
+impl Collector for InnerHitsCollector {
+    type Fruit = InnerHits;
+    type Child = InnerHitsSegmentCollector;
+
+    fn for_segment(&self, segment_ord: SegmentOrdinal, reader: &SegmentReader) -> tantivy::Result<Self::Child> {
+        let groups = self.spec.segment(reader)?;
+        let mut wanted = HashMap::new();
+        for (i, group) in self.groups.iter().enumerate() {
+            if let Some(key) = groups.segment_key(group)? { wanted.insert(key, i); }
+        }
+        Ok(InnerHitsSegmentCollector {
+            segment_ord,
+            groups,
+            size: self.size,
+            wanted,
+            hits: vec![Vec::new(); self.groups.len()],
+            keys: self.groups.clone(),
+        })
+    }
+
+    fn requires_scoring(&self) -> bool { self.spec.sort.is_none() }
+
+    fn merge_fruits(&self, fruits: Vec<InnerHits>) -> tantivy::Result<InnerHits> {
+        let mut merged = InnerHits::new();
+        for fruit in fruits {
+            for (key, hits) in fruit {
+                let top = merged.entry(key).or_default();
+                for hit in hits { keep(top, self.size, hit); }
+            }
+        }
+        Ok(merged)
+    }
+}
+
+pub(crate) struct InnerHitsSegmentCollector {
+    segment_ord: SegmentOrdinal,
+    groups: SegmentGroups,
+    size: usize,
+    // Segment key to index into `keys` and `hits`.
+    wanted: HashMap<Option<u64>, usize>,
+    hits: Vec<Vec<(Rank, DocId)>>,
+    keys: Arc<Vec<GroupKey>>,
+}
+
+impl SegmentCollector for InnerHitsSegmentCollector {
+    type Fruit = InnerHits;
+
+    fn collect(&mut self, doc: DocId, score: Score) {
+        let Some(&i) = self.wanted.get(&self.groups.key(doc)) else { return };
+        let rank = self.groups.rank(doc, score);
+        keep(&mut self.hits[i], self.size, (rank, doc));
+    }
+
+    fn harvest(self) -> InnerHits {
+        self.hits.into_iter().enumerate()
+            .filter(|(_, hits)| !hits.is_empty())
+            .map(|(i, hits)| {
+                let hits = hits.into_iter().map(|(rank, doc)| (rank, DocAddress::new(self.segment_ord, doc))).collect();
+                (self.keys[i].clone(), hits)
+            })
+            .collect()
+    }
+}
+
+/// Insert `hit` into `top`, keeping the `size` best. Ties keep the earlier hit.
+fn keep<D>(top: &mut Vec<(Rank, D)>, size: usize, hit: (Rank, D)) {
+    if top.len() == size && top.last().is_some_and(|last| last.0 >= hit.0) { return; }
+    let pos = top.partition_point(|h| h.0 >= hit.0);
+    top.insert(pos, hit);
+    top.truncate(size);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::segmented;
+    use tantivy::collector::Collector;
+    use tantivy::query::{AllQuery, Query, TermQuery};
+    use tantivy::schema::{IndexRecordOption, Schema, FAST, STRING, TEXT};
+    use tantivy::{Searcher, TantivyDocument, Term};
+
+    const BIG: i64 = 9_007_199_254_740_993;
+
+    /// Three segments of `(family, n)`; `n` is unique, so it names the doc.
+    fn index() -> Searcher {
+        let mut builder = Schema::builder();
+        let family = builder.add_text_field("family", STRING | FAST);
+        let n = builder.add_i64_field("n", FAST);
+        let body = builder.add_text_field("body", TEXT);
+        let segments: [&[(Option<&str>, i64, &str)]; 3] = [
+            &[(Some("f1"), 1, "breach"), (Some("f2"), 5, "breach of contract"), (None, 3, "contract")],
+            &[(Some("f1"), 7, "breach of the implied contract terms"), (Some("f3"), 2, "terms"), (None, 9, "breach"), (Some("f4"), BIG, "terms")],
+            &[(Some("f2"), 4, "breach"), (Some("f4"), BIG - 1, "terms")],
+        ];
+        let docs = segments.iter().map(|docs| docs.iter().map(|&(f, v, text)| {
+            let mut d = TantivyDocument::default();
+            if let Some(f) = f { d.add_text(family, f); }
+            d.add_i64(n, v);
+            d.add_text(body, text);
+            d
+        }).collect()).collect();
+        segmented(builder.build(), docs)
+    }
+
+    fn field(json: &str) -> FieldDef { serde_json::from_str(json).unwrap() }
+
+    /// Group by `family`, ranked by score or by `n`.
+    fn spec(sort: Option<bool>) -> GroupSpec {
+        GroupSpec {
+            field: ("family".to_string(), field(r#"{"name":"family","type":"text","fast":true,"tokenizer":"raw"}"#)),
+            sort: sort.map(|desc| ("n".to_string(), field(r#"{"name":"n","type":"i64","fast":true}"#), desc)),
+            functions: None,
+        }
+    }
+
+    fn n(searcher: &Searcher, addr: DocAddress) -> i64 {
+        searcher.segment_reader(addr.segment_ord).fast_fields().i64("n").unwrap().first(addr.doc_id).unwrap()
+    }
+
+    fn collapse(searcher: &Searcher, q: &dyn Query, sort: Option<bool>, top: usize) -> (Vec<(GroupKey, i64)>, usize) {
+        let collapsed = searcher.search(q, &CollapseCollector { spec: spec(sort), top }).unwrap();
+        let groups = collapsed.groups.into_iter().map(|(key, _, addr)| (key, n(searcher, addr))).collect();
+        (groups, collapsed.total_groups)
+    }
+
+    fn key(s: &str) -> GroupKey { GroupKey::Str(s.to_string()) }
+
+    #[test]
+    fn best_hit_per_group() {
+        let searcher = index();
+        let (groups, total) = collapse(&searcher, &AllQuery, Some(true), 10);
+        assert_eq!(groups, [(key("f4"), BIG), (GroupKey::Missing, 9), (key("f1"), 7), (key("f2"), 5), (key("f3"), 2)]);
+        assert_eq!(total, 5);
+
+        let (groups, total) = collapse(&searcher, &AllQuery, Some(false), 10);
+        assert_eq!(groups, [(key("f1"), 1), (key("f3"), 2), (GroupKey::Missing, 3), (key("f2"), 4), (key("f4"), BIG - 1)]);
+        assert_eq!(total, 5);
+    }
+
+    #[test]
+    fn keeps_only_top_groups_but_counts_all() {
+        let searcher = index();
+        let (groups, total) = collapse(&searcher, &AllQuery, Some(true), 2);
+        assert_eq!(groups, [(key("f4"), BIG), (GroupKey::Missing, 9)]);
+        assert_eq!(total, 5);
+
+        let (groups, total) = collapse(&searcher, &AllQuery, Some(false), 0);
+        assert!(groups.is_empty());
+        assert_eq!(total, 5);
+    }
+
+    #[test]
+    fn ranks_by_score() {
+        let searcher = index();
+        let q = TermQuery::new(Term::from_field_text(searcher.schema().get_field("body").unwrap(), "breach"), IndexRecordOption::WithFreqs);
+        let (groups, total) = collapse(&searcher, &q, None, 10);
+        // Short fields score highest; the f1 doc with the longer body loses.
+        let mut best: Vec<_> = groups.iter().map(|(_, n)| *n).collect();
+        best.sort();
+        assert_eq!(best, [1, 4, 9]);
+        assert_eq!(groups.iter().find(|(k, _)| *k == key("f1")).unwrap().1, 1);
+        assert_eq!(total, 3);
+    }
+
+    #[test]
+    fn i64_sort_values_stay_exact() {
+        let searcher = index();
+        let desc = searcher.search(&AllQuery, &CollapseCollector { spec: spec(Some(true)), top: 1 }).unwrap();
+        assert_eq!(desc.groups[0].1.value(true), HitValue::I64(BIG));
+        let asc = searcher.search(&AllQuery, &CollapseCollector { spec: spec(Some(false)), top: 5 }).unwrap();
+        assert_eq!(asc.groups[4].1.value(false), HitValue::I64(BIG - 1));
+        assert!(Rank::I64(BIG) > Rank::I64(BIG - 1));
+        assert!(Rank::Missing < Rank::I64(i64::MIN));
+    }
+
+    #[test]
+    fn inner_hits_of_selected_groups() {
+        let searcher = index();
+        let groups = Arc::new(vec![key("f1"), GroupKey::Missing, key("f2"), key("f9")]);
+        let collector = InnerHitsCollector { spec: spec(Some(true)), size: 2, groups };
+        assert!(!collector.requires_scoring());
+        let inner = searcher.search(&AllQuery, &collector).unwrap();
+        let ns = |k: &GroupKey| -> Vec<i64> { inner[k].iter().map(|&(_, addr)| n(&searcher, addr)).collect() };
+        assert_eq!(ns(&key("f1")), [7, 1]);
+        assert_eq!(ns(&GroupKey::Missing), [9, 3]);
+        assert_eq!(ns(&key("f2")), [5, 4]);
+        // Groups outside the page, or without hits, are left out.
+        assert_eq!(inner.len(), 3);
+
+        let collector = InnerHitsCollector { spec: spec(Some(false)), size: 3, groups: Arc::new(vec![key("f4")]) };
+        let inner = searcher.search(&AllQuery, &collector).unwrap();
+        let values: Vec<_> = inner[&key("f4")].iter().map(|(rank, _)| rank.value(false)).collect();
+        assert_eq!(values, [HitValue::I64(BIG - 1), HitValue::I64(BIG)]);
+    }
+}
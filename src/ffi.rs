@@ -1,96 +1,744 @@
-//! Minimal generic C FFI — only 7 functions needed.
+//! Minimal generic C FFI.
+//!
+//! Errors are written to the `err` out-parameter as JSON
+//! `{"code":N,"kind":"...","message":"...","path":"..."}` (see `crate::Error`).
+//!
+//! Every exported function runs inside `guard`, so a panic never unwinds into
+//! the caller: it is reported as a `panic` error (code 7), and the function
+//! returns `ERR_PANIC` (integer returns) or null / 0 (pointer / handle returns).
+//!
+//! Document and query payloads can be passed either as NUL-terminated C strings
+//! or, via the `*_buf` variants, as `(ptr, len)` byte slices with results
+//! returned in an owned `TantivyBuf` (free with `tantivy_free_buf`). Searches
+//! take a `(ptr, len)` query through `tantivy_search_encoded`, whose
+//! `encoding` argument picks the result format.
+//!
+//! Indexes and snapshots are exposed as generation-tagged `u64` handles from a
+//! global registry, never as raw pointers. 0, freed and stale handles are
+//! reported as errors.
 
-use crate::TantivyIndex;
+// C string and out-parameter pointers are still the caller's contract.
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
+use crate::registry::Registry;
+use crate::{Error, ResultEncoding, Snapshot, TantivyIndex};
+use serde::Serialize;
+use std::any::Any;
 use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
 use std::ptr;
+use std::ops::Deref;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+static INDEXES: Mutex<Registry<OpenIndex>> = Mutex::new(Registry::new());
+static SNAPSHOTS: Mutex<Registry<Snapshot>> = Mutex::new(Registry::new());
+
+/// Returned by integer-valued functions on an ordinary error.
+const ERR: i32 = -1;
+/// Returned by integer-valued functions when a panic was caught; `err` then
+/// has kind `panic` (code 7), which pointer / handle returns rely on instead.
+const ERR_PANIC: i32 = -2;
 
-fn set_err(out: *mut *mut c_char, msg: &str) {
+fn set_err(out: *mut *mut c_char, e: &Error) {
     if !out.is_null() {
-        if let Ok(c) = CString::new(msg) { unsafe { *out = c.into_raw() }; }
+        if let Ok(c) = CString::new(e.to_json()) { unsafe { *out = c.into_raw() }; }
     }
 }
 
-fn cstr<'a>(p: *const c_char) -> Result<&'a str, String> {
+fn cstr<'a>(p: *const c_char) -> Result<&'a str, Error> {
     if p.is_null() { return Ok(""); }
-    unsafe { CStr::from_ptr(p) }.to_str().map_err(|e| e.to_string())
+    unsafe { CStr::from_ptr(p) }.to_str().map_err(|e| Error::bad_request(e.to_string()))
+}
+
+/// Read a `(ptr, len)` UTF-8 slice. Null with len 0 reads as "".
+fn bytes<'a>(p: *const u8, len: usize) -> Result<&'a str, Error> {
+    if p.is_null() {
+        if len == 0 { return Ok(""); }
+        return Err(Error::bad_request("null buffer with non-zero length"));
+    }
+    let b = unsafe { std::slice::from_raw_parts(p, len) };
+    std::str::from_utf8(b).map_err(|e| Error::bad_request(e.to_string()))
+}
+
+/// Owned byte buffer handed to the caller. `data` is null on error.
+#[repr(C)]
+pub struct TantivyBuf {
+    pub data: *mut u8,
+    pub len: usize,
+    pub cap: usize,
+}
+
+impl TantivyBuf {
+    fn null() -> Self { TantivyBuf { data: ptr::null_mut(), len: 0, cap: 0 } }
+
+    fn from_vec(v: Vec<u8>) -> Self {
+        let mut v = std::mem::ManuallyDrop::new(v);
+        TantivyBuf { data: v.as_mut_ptr(), len: v.len(), cap: v.capacity() }
+    }
+}
+
+/// Serialize a reply. Failing to is an internal error, never an empty reply.
+fn to_json<T: Serialize>(v: &T) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(v).map_err(|e| Error::internal(format!("serialize: {}", e)))
+}
+
+/// Hand JSON to the caller as a C string (freed with tantivy_free_string).
+fn ret_json(json: Vec<u8>) -> Result<*mut c_char, Error> {
+    CString::new(json).map(CString::into_raw).map_err(|e| Error::internal(format!("serialize: {}", e)))
+}
+
+/// An index in the registry. In-flight calls hold clones of its `Arc`; the
+/// last one to go drops `index`, releasing the writer lock, and then `released`,
+/// which wakes `tantivy_free_index`.
+struct OpenIndex {
+    index: TantivyIndex,
+    released: Released,
+}
+
+impl OpenIndex {
+    fn new(index: TantivyIndex) -> Self { OpenIndex { index, released: Released(Arc::default()) } }
+}
+
+impl Deref for OpenIndex {
+    type Target = TantivyIndex;
+    fn deref(&self) -> &TantivyIndex { &self.index }
+}
+
+/// Set to true, with waiters notified, when dropped.
+struct Released(Arc<(Mutex<bool>, Condvar)>);
+
+impl Released {
+    fn wait(signal: &(Mutex<bool>, Condvar)) {
+        let mut done = lock(&signal.0);
+        while !*done { done = signal.1.wait(done).unwrap_or_else(|e| e.into_inner()); }
+    }
+}
+
+impl Drop for Released {
+    fn drop(&mut self) {
+        *lock(&self.0 .0) = true;
+        self.0 .1.notify_all();
+    }
+}
+
+// Registry operations never panic mid-update, so a poisoned lock is still consistent.
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn index(h: u64) -> Result<Arc<OpenIndex>, Error> {
+    lock(&INDEXES).get(h).ok_or_else(|| Error::not_found(format!("invalid index handle: {}", h)))
+}
+
+fn snapshot(h: u64) -> Result<Arc<Snapshot>, Error> {
+    lock(&SNAPSHOTS).get(h).ok_or_else(|| Error::not_found(format!("invalid snapshot handle: {}", h)))
 }
 
-fn ret_json(s: &str) -> *mut c_char {
-    CString::new(s).unwrap_or_default().into_raw()
+fn panic_message(p: &(dyn Any + Send)) -> String {
+    if let Some(s) = p.downcast_ref::<&str>() { return s.to_string(); }
+    if let Some(s) = p.downcast_ref::<String>() { return s.clone(); }
+    "unknown panic".to_string()
+}
+
+/// Run `f`, mapping `Err` to `on_err` and a panic to `on_panic`, both with `err` set.
+fn guard<T>(err: *mut *mut c_char, on_err: T, on_panic: T, f: impl FnOnce() -> Result<T, Error>) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(v)) => v,
+        Ok(Err(e)) => { set_err(err, &e); on_err }
+        Err(p) => {
+            set_err(err, &Error::panic(format!("panic: {}", panic_message(p.as_ref()))));
+            on_panic
+        }
+    }
 }
 
 #[no_mangle]
 pub extern "C" fn tantivy_free_string(s: *mut c_char) {
-    if !s.is_null() { unsafe { let _ = CString::from_raw(s); } }
+    guard(ptr::null_mut(), (), (), || {
+        if !s.is_null() { unsafe { let _ = CString::from_raw(s); } }
+        Ok(())
+    })
 }
 
+/// Free a buffer returned by a `*_buf` function. Freeing a null buffer is a no-op.
 #[no_mangle]
-pub extern "C" fn tantivy_free_index(idx: *mut TantivyIndex) {
-    if !idx.is_null() { unsafe { let _ = Box::from_raw(idx); } }
+pub extern "C" fn tantivy_free_buf(buf: TantivyBuf) {
+    guard(ptr::null_mut(), (), (), || {
+        if !buf.data.is_null() { unsafe { let _ = Vec::from_raw_parts(buf.data, buf.len, buf.cap); } }
+        Ok(())
+    })
 }
 
-/// Create index with JSON schema. Returns handle or null.
+/// Free an index handle. Freeing 0 or an already-freed handle is a no-op.
+/// Wakes callers blocked in `tantivy_wait_for_opstamp`, then blocks until
+/// calls still running on other threads have returned and the index is
+/// dropped, so the writer lock is released and the path can be reopened as
+/// soon as this returns.
+#[no_mangle]
+pub extern "C" fn tantivy_free_index(idx: u64) {
+    guard(ptr::null_mut(), (), (), || {
+        // Take the entry out under the lock; new calls now fail with an invalid handle.
+        let Some(removed) = lock(&INDEXES).remove(idx) else { return Ok(()) };
+        let released = removed.released.0.clone();
+        let closed = removed.close();
+        // Whichever reference goes last, ours or an in-flight call's, drops the index.
+        drop(removed);
+        Released::wait(&released);
+        closed
+    })
+}
+
+/// Create index with JSON schema. Returns handle, or 0 on error.
 #[no_mangle]
 pub extern "C" fn tantivy_create_index(
     path: *const c_char, schema_json: *const c_char, err: *mut *mut c_char,
-) -> *mut TantivyIndex {
-    let r = (|| -> Result<*mut TantivyIndex, String> {
+) -> u64 {
+    guard(err, 0, 0, || {
         let p = cstr(path)?;
         let s = cstr(schema_json)?;
         let idx = TantivyIndex::create(p, s)?;
-        Ok(Box::into_raw(Box::new(idx)))
-    })();
-    match r { Ok(p) => p, Err(e) => { set_err(err, &e); ptr::null_mut() } }
+        Ok(lock(&INDEXES).insert(OpenIndex::new(idx)))
+    })
 }
 
 /// Open existing index (schema read from _schema.json inside index dir).
 #[no_mangle]
 pub extern "C" fn tantivy_open_index(
     path: *const c_char, err: *mut *mut c_char,
-) -> *mut TantivyIndex {
-    let r = (|| -> Result<*mut TantivyIndex, String> {
+) -> u64 {
+    guard(err, 0, 0, || {
         let p = cstr(path)?;
         let idx = TantivyIndex::open(p)?;
-        Ok(Box::into_raw(Box::new(idx)))
-    })();
-    match r { Ok(p) => p, Err(e) => { set_err(err, &e); ptr::null_mut() } }
+        Ok(lock(&INDEXES).insert(OpenIndex::new(idx)))
+    })
+}
+
+/// Create index with JSON schema and `IndexOptions` JSON (null for defaults).
+#[no_mangle]
+pub extern "C" fn tantivy_create_index_with_options(
+    path: *const c_char, schema_json: *const c_char, options_json: *const c_char, err: *mut *mut c_char,
+) -> u64 {
+    guard(err, 0, 0, || {
+        let idx = TantivyIndex::create_with_options(cstr(path)?, cstr(schema_json)?, cstr(options_json)?)?;
+        Ok(lock(&INDEXES).insert(OpenIndex::new(idx)))
+    })
 }
 
-/// Add a JSON document. Returns 0 on success, -1 on error.
+/// Open existing index with `IndexOptions` JSON (null for defaults).
+#[no_mangle]
+pub extern "C" fn tantivy_open_index_with_options(
+    path: *const c_char, options_json: *const c_char, err: *mut *mut c_char,
+) -> u64 {
+    guard(err, 0, 0, || {
+        let idx = TantivyIndex::open_with_options(cstr(path)?, cstr(options_json)?)?;
+        Ok(lock(&INDEXES).insert(OpenIndex::new(idx)))
+    })
+}
+
+/// Add a JSON document. Returns the opstamp (>= 0) on success, -1 on error, -2 on panic.
 #[no_mangle]
 pub extern "C" fn tantivy_add_doc(
-    idx: *mut TantivyIndex, doc_json: *const c_char, err: *mut *mut c_char,
+    idx: u64, doc_json: *const c_char, err: *mut *mut c_char,
+) -> i64 {
+    guard(err, ERR as i64, ERR_PANIC as i64, || {
+        let idx = index(idx)?;
+        Ok(idx.add_doc(cstr(doc_json)?)? as i64)
+    })
+}
+
+/// Add a JSON document passed as a `(ptr, len)` byte slice. Same returns as tantivy_add_doc.
+#[no_mangle]
+pub extern "C" fn tantivy_add_doc_buf(
+    idx: u64, doc_json: *const u8, doc_len: usize, err: *mut *mut c_char,
+) -> i64 {
+    guard(err, ERR as i64, ERR_PANIC as i64, || {
+        let idx = index(idx)?;
+        Ok(idx.add_doc(bytes(doc_json, doc_len)?)? as i64)
+    })
+}
+
+/// Delete documents where `field` equals the JSON-encoded `value_json`.
+/// Returns the opstamp (>= 0) on success, -1 on error, -2 on panic.
+#[no_mangle]
+pub extern "C" fn tantivy_delete_term(
+    idx: u64, field: *const c_char, value_json: *const c_char, err: *mut *mut c_char,
+) -> i64 {
+    guard(err, ERR as i64, ERR_PANIC as i64, || {
+        let idx = index(idx)?;
+        Ok(idx.delete_term(cstr(field)?, &term_value(cstr(value_json)?)?)? as i64)
+    })
+}
+
+/// tantivy_delete_term with `field` and `value_json` passed as `(ptr, len)` byte slices.
+#[no_mangle]
+pub extern "C" fn tantivy_delete_term_buf(
+    idx: u64, field: *const u8, field_len: usize, value_json: *const u8, value_len: usize, err: *mut *mut c_char,
+) -> i64 {
+    guard(err, ERR as i64, ERR_PANIC as i64, || {
+        let idx = index(idx)?;
+        Ok(idx.delete_term(bytes(field, field_len)?, &term_value(bytes(value_json, value_len)?)?)? as i64)
+    })
+}
+
+fn term_value(json: &str) -> Result<serde_json::Value, Error> {
+    serde_json::from_str(json).map_err(|e| Error::bad_request(format!("value: {}", e)))
+}
+
+/// Commit pending writes. Returns the commit opstamp (>= 0) on success, -1 on error, -2 on panic.
+#[no_mangle]
+pub extern "C" fn tantivy_commit(idx: u64, err: *mut *mut c_char) -> i64 {
+    guard(err, ERR as i64, ERR_PANIC as i64, || {
+        let idx = index(idx)?;
+        Ok(idx.commit()? as i64)
+    })
+}
+
+/// Reload the reader so searches see all commits. Returns 0 on success, -1 on error, -2 on panic.
+#[no_mangle]
+pub extern "C" fn tantivy_refresh(idx: u64, err: *mut *mut c_char) -> i32 {
+    guard(err, ERR, ERR_PANIC, || {
+        let idx = index(idx)?;
+        idx.refresh()?;
+        Ok(0)
+    })
+}
+
+/// Configure background commits: {"max_docs":N,"interval_ms":T}; zeros disable.
+/// Returns 0 on success, -1 on error, -2 on panic.
+#[no_mangle]
+pub extern "C" fn tantivy_set_auto_commit(
+    idx: u64, config_json: *const c_char, err: *mut *mut c_char,
 ) -> i32 {
-    let idx = unsafe { &*idx };
-    match cstr(doc_json).and_then(|s| idx.add_doc(s)) {
-        Ok(()) => 0,
-        Err(e) => { set_err(err, &e); -1 }
-    }
+    guard(err, ERR, ERR_PANIC, || {
+        let idx = index(idx)?;
+        idx.set_auto_commit(cstr(config_json)?)?;
+        Ok(0)
+    })
 }
 
-/// Commit pending writes. Returns 0 on success, -1 on error.
+/// Last background commit error as error JSON, or null if none
+/// (caller frees with tantivy_free_string).
 #[no_mangle]
-pub extern "C" fn tantivy_commit(idx: *mut TantivyIndex, err: *mut *mut c_char) -> i32 {
-    let idx = unsafe { &*idx };
-    match idx.commit() { Ok(()) => 0, Err(e) => { set_err(err, &e); -1 } }
+pub extern "C" fn tantivy_last_error(idx: u64) -> *mut c_char {
+    guard(ptr::null_mut(), ptr::null_mut(), ptr::null_mut(), || {
+        Ok(match index(idx)?.last_error() {
+            Some(e) => ret_json(e.to_json().into_bytes())?,
+            None => ptr::null_mut(),
+        })
+    })
 }
 
-/// Get number of documents in the index.
+/// Wait until searches see every operation up to `opstamp`.
+/// Returns 1 if visible, 0 on timeout, -1 on error, -2 on panic.
 #[no_mangle]
-pub extern "C" fn tantivy_num_docs(idx: *mut TantivyIndex) -> u64 {
-    unsafe { &*idx }.num_docs()
+pub extern "C" fn tantivy_wait_for_opstamp(
+    idx: u64, opstamp: u64, timeout_ms: u64, err: *mut *mut c_char,
+) -> i32 {
+    guard(err, ERR, ERR_PANIC, || {
+        let idx = index(idx)?;
+        Ok(if idx.wait_for_opstamp(opstamp, timeout_ms)? { 1 } else { 0 })
+    })
+}
+
+/// Get number of documents in the index (0 for an invalid handle or a caught panic).
+#[no_mangle]
+pub extern "C" fn tantivy_num_docs(idx: u64) -> u64 {
+    guard(ptr::null_mut(), 0, 0, || Ok(index(idx)?.num_docs()))
 }
 
 /// Search with JSON query DSL. Returns JSON results string (caller frees with tantivy_free_string).
 #[no_mangle]
 pub extern "C" fn tantivy_search(
-    idx: *mut TantivyIndex, query_json: *const c_char, err: *mut *mut c_char,
+    idx: u64, query_json: *const c_char, err: *mut *mut c_char,
 ) -> *mut c_char {
-    let idx = unsafe { &*idx };
-    let r = cstr(query_json).and_then(|s| idx.search(s));
-    match r {
-        Ok(results) => ret_json(&serde_json::to_string(&results).unwrap_or_default()),
-        Err(e) => { set_err(err, &e); ptr::null_mut() }
+    guard(err, ptr::null_mut(), ptr::null_mut(), || {
+        let idx = index(idx)?;
+        ret_json(to_json(&idx.search(cstr(query_json)?)?)?)
+    })
+}
+
+/// Parse and build a query without running it. Returns JSON
+/// {request, fields, debug, warnings}; errors carry the offending node's path.
+#[no_mangle]
+pub extern "C" fn tantivy_validate_query(
+    idx: u64, query_json: *const c_char, err: *mut *mut c_char,
+) -> *mut c_char {
+    guard(err, ptr::null_mut(), ptr::null_mut(), || {
+        let idx = index(idx)?;
+        let v = idx.validate_query(cstr(query_json)?)?;
+        ret_json(to_json(&v)?)
+    })
+}
+
+/// tantivy_validate_query with a `(ptr, len)` query, returning the JSON in an
+/// owned buffer (free with tantivy_free_buf); `data` is null on error.
+#[no_mangle]
+pub extern "C" fn tantivy_validate_query_buf(
+    idx: u64, query_json: *const u8, query_len: usize, err: *mut *mut c_char,
+) -> TantivyBuf {
+    guard(err, TantivyBuf::null(), TantivyBuf::null(), || {
+        let idx = index(idx)?;
+        let v = idx.validate_query(bytes(query_json, query_len)?)?;
+        Ok(TantivyBuf::from_vec(to_json(&v)?))
+    })
+}
+
+/// Pin the current searcher generation. Returns a snapshot handle
+/// (release with tantivy_release_snapshot), or 0 on error.
+#[no_mangle]
+pub extern "C" fn tantivy_acquire_snapshot(idx: u64, err: *mut *mut c_char) -> u64 {
+    guard(err, 0, 0, || {
+        let snap = index(idx)?.snapshot();
+        Ok(lock(&SNAPSHOTS).insert(snap))
+    })
+}
+
+/// Release a snapshot handle. Releasing 0 or an already-released handle is a no-op.
+#[no_mangle]
+pub extern "C" fn tantivy_release_snapshot(snap: u64) {
+    guard(ptr::null_mut(), (), (), || {
+        let removed = lock(&SNAPSHOTS).remove(snap);
+        drop(removed);
+        Ok(())
+    })
+}
+
+/// Number of documents visible in a snapshot (0 for an invalid handle or a caught panic).
+#[no_mangle]
+pub extern "C" fn tantivy_snapshot_num_docs(snap: u64) -> u64 {
+    guard(ptr::null_mut(), 0, 0, || Ok(snapshot(snap)?.num_docs()))
+}
+
+/// Search against a snapshot taken from the same index. Returns JSON results string.
+#[no_mangle]
+pub extern "C" fn tantivy_search_snapshot(
+    idx: u64, snap: u64, query_json: *const c_char, err: *mut *mut c_char,
+) -> *mut c_char {
+    guard(err, ptr::null_mut(), ptr::null_mut(), || {
+        let idx = index(idx)?;
+        let snap = snapshot(snap)?;
+        ret_json(to_json(&idx.search_snapshot(&snap, cstr(query_json)?)?)?)
+    })
+}
+
+/// Search with a `(ptr, len)` query and results in the given encoding (0 JSON,
+/// 1 MessagePack, 2 column-oriented MessagePack), returned in an owned buffer
+/// (free with tantivy_free_buf); `data` is null on error. Pass `snap` = 0 to
+/// search the live reader, or a snapshot handle to search that snapshot.
+#[no_mangle]
+pub extern "C" fn tantivy_search_encoded(
+    idx: u64, snap: u64, query_json: *const u8, query_len: usize, encoding: u32, err: *mut *mut c_char,
+) -> TantivyBuf {
+    guard(err, TantivyBuf::null(), TantivyBuf::null(), || {
+        let idx = index(idx)?;
+        let enc = ResultEncoding::from_code(encoding)?;
+        let q = bytes(query_json, query_len)?;
+        let results = match snap {
+            0 => idx.search(q)?,
+            h => idx.search_snapshot(&*snapshot(h)?, q)?,
+        };
+        Ok(TantivyBuf::from_vec(enc.encode(&results)?))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use tempfile::TempDir;
+
+    const SCHEMA: &str = r#"{"fields":[{"name":"id","type":"text","tokenizer":"raw"},{"name":"title","type":"text"}]}"#;
+
+    fn c(s: &str) -> CString { CString::new(s).unwrap() }
+
+    /// Take ownership of a returned C string.
+    fn take(p: *mut c_char) -> Option<String> {
+        if p.is_null() { return None; }
+        let s = unsafe { CStr::from_ptr(p) }.to_str().unwrap().to_string();
+        tantivy_free_string(p);
+        Some(s)
+    }
+
+    fn create(dir: &TempDir) -> u64 {
+        let mut err = ptr::null_mut();
+        let h = tantivy_create_index(c(dir.path().to_str().unwrap()).as_ptr(), c(SCHEMA).as_ptr(), &mut err);
+        assert_ne!(h, 0, "{:?}", take(err));
+        h
+    }
+
+    #[test]
+    fn panics_surface_as_panic_errors() {
+        let mut err = ptr::null_mut();
+        let r: i32 = guard(&mut err, ERR, ERR_PANIC, || panic!("boom"));
+        assert_eq!(r, ERR_PANIC);
+        let e: Error = serde_json::from_str(&take(err).unwrap()).unwrap();
+        assert_eq!((e.code, e.kind, e.message.as_str()), (7, crate::ErrorKind::Panic, "panic: boom"));
+
+        // Pointer and handle returns give null / 0 with the same error.
+        let mut err = ptr::null_mut();
+        let p: *mut c_char = guard(&mut err, ptr::null_mut(), ptr::null_mut(), || panic!("{}", 42));
+        assert!(p.is_null());
+        let e: Error = serde_json::from_str(&take(err).unwrap()).unwrap();
+        assert_eq!((e.code, e.message.as_str()), (7, "panic: 42"));
+
+        // Ordinary errors keep their own kind and return value.
+        let mut err = ptr::null_mut();
+        let r: i32 = guard(&mut err, ERR, ERR_PANIC, || Err(Error::bad_request("no")));
+        assert_eq!(r, ERR);
+        assert_eq!(serde_json::from_str::<Error>(&take(err).unwrap()).unwrap().code, 1);
+    }
+
+    #[test]
+    fn errors_are_structured_json() {
+        let dir = TempDir::new().unwrap();
+        let h = create(&dir);
+        // The raw error object: code, kind and message, plus path when known.
+        let raw = |query: &str| {
+            let mut err = ptr::null_mut();
+            assert!(tantivy_search(h, c(query).as_ptr(), &mut err).is_null());
+            serde_json::from_str::<serde_json::Value>(&take(err).unwrap()).unwrap()
+        };
+        let v = raw("{");
+        assert_eq!(v.as_object().unwrap().keys().collect::<Vec<_>>(), ["code", "kind", "message"]);
+        assert_eq!((v["code"].as_u64(), v["kind"].as_str()), (Some(1), Some("bad_request")));
+        let v = raw(r#"{"type":"bool","must":[{"type":"all"},{"type":"term_match","field":"nope","value":"x"}]}"#);
+        assert_eq!(v.as_object().unwrap().keys().collect::<Vec<_>>(), ["code", "kind", "message", "path"]);
+        assert_eq!((v["code"].as_u64(), v["kind"].as_str()), (Some(3), Some("schema_mismatch")));
+        assert_eq!((v["message"].as_str(), v["path"].as_str()), (Some("unknown field: nope"), Some("must[1].field")));
+
+        // A second writer on the same directory, and a directory with no index.
+        let mut err = ptr::null_mut();
+        assert_eq!(tantivy_open_index(c(dir.path().to_str().unwrap()).as_ptr(), &mut err), 0);
+        let e: Error = serde_json::from_str(&take(err).unwrap()).unwrap();
+        assert_eq!((e.code, e.kind), (5, crate::ErrorKind::LockBusy));
+        let mut err = ptr::null_mut();
+        assert_eq!(tantivy_open_index(c(dir.path().join("missing").to_str().unwrap()).as_ptr(), &mut err), 0);
+        let e: Error = serde_json::from_str(&take(err).unwrap()).unwrap();
+        assert_eq!((e.code, e.kind), (2, crate::ErrorKind::NotFound));
+
+        // Calls on a freed handle, and a null error pointer, which is skipped.
+        tantivy_free_index(h);
+        let mut err = ptr::null_mut();
+        assert_eq!(tantivy_add_doc(h, c(r#"{"id":"a"}"#).as_ptr(), &mut err), ERR as i64);
+        let e: Error = serde_json::from_str(&take(err).unwrap()).unwrap();
+        assert_eq!((e.code, e.kind, e.path), (2, crate::ErrorKind::NotFound, None));
+        assert_eq!(tantivy_add_doc(h, c(r#"{"id":"a"}"#).as_ptr(), ptr::null_mut()), ERR as i64);
+    }
+
+    fn add(h: u64, id: &str) {
+        let mut err = ptr::null_mut();
+        let doc = format!(r#"{{"id":"{}","title":"doc {}"}}"#, id, id);
+        assert!(tantivy_add_doc(h, c(&doc).as_ptr(), &mut err) >= 0, "{:?}", take(err));
+    }
+
+    fn commit(h: u64) {
+        let mut err = ptr::null_mut();
+        assert!(tantivy_commit(h, &mut err) >= 0, "{:?}", take(err));
+    }
+
+    /// Search `snap` (0 for the live reader), returning the hit ids and total_count.
+    fn search(h: u64, snap: u64, query: &str) -> Result<(Vec<String>, u64), Error> {
+        let mut err = ptr::null_mut();
+        let out = match snap {
+            0 => tantivy_search(h, c(query).as_ptr(), &mut err),
+            s => tantivy_search_snapshot(h, s, c(query).as_ptr(), &mut err),
+        };
+        let Some(json) = take(out) else { return Err(serde_json::from_str(&take(err).unwrap()).unwrap()) };
+        let v: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let ids = v["results"].as_array().unwrap().iter().map(|hit| hit["id"].as_str().unwrap().to_string()).collect();
+        Ok((ids, v["total_count"].as_u64().unwrap()))
+    }
+
+    #[test]
+    fn snapshot_pages_stay_stable_across_commits() {
+        let dir = TempDir::new().unwrap();
+        let h = create(&dir);
+        for i in 0..5 { add(h, &format!("a{}", i)); }
+        commit(h);
+        let mut err = ptr::null_mut();
+        let snap = tantivy_acquire_snapshot(h, &mut err);
+        assert_ne!(snap, 0);
+        let page = |offset: usize| search(h, snap, &format!(r#"{{"type":"all","limit":2,"offset":{}}}"#, offset)).unwrap();
+        let (all, total) = search(h, snap, r#"{"type":"all","limit":10}"#).unwrap();
+        assert_eq!(total, 5);
+
+        let (first, _) = page(0);
+        for i in 0..3 { add(h, &format!("b{}", i)); }
+        commit(h);
+        assert_eq!(search(h, 0, r#"{"type":"all"}"#).unwrap().1, 8);
+        // The snapshot still pages through the five documents it pinned.
+        let (second, total) = page(2);
+        let (third, _) = page(4);
+        assert_eq!(total, 5);
+        assert_eq!(tantivy_snapshot_num_docs(snap), 5);
+        assert_eq!([first, second, third].concat(), all);
+
+        tantivy_release_snapshot(snap);
+        let e = search(h, snap, r#"{"type":"all"}"#).unwrap_err();
+        assert_eq!(e.kind, crate::ErrorKind::NotFound);
+        // Releasing again is a no-op.
+        tantivy_release_snapshot(snap);
+        tantivy_free_index(h);
+    }
+
+    #[test]
+    fn snapshot_of_another_index_is_rejected() {
+        let (dir_a, dir_b) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let (a, b) = (create(&dir_a), create(&dir_b));
+        add(a, "a0");
+        commit(a);
+        let mut err = ptr::null_mut();
+        let snap = tantivy_acquire_snapshot(a, &mut err);
+        let e = search(b, snap, r#"{"type":"all"}"#).unwrap_err();
+        assert_eq!(e.kind, crate::ErrorKind::BadRequest);
+        assert_eq!(search(a, snap, r#"{"type":"all"}"#).unwrap().1, 1);
+        tantivy_release_snapshot(snap);
+        tantivy_free_index(a);
+        tantivy_free_index(b);
+    }
+
+    fn open(dir: &TempDir) -> u64 {
+        let mut err = ptr::null_mut();
+        let h = tantivy_open_index(c(dir.path().to_str().unwrap()).as_ptr(), &mut err);
+        assert_ne!(h, 0, "{:?}", take(err));
+        h
+    }
+
+    #[test]
+    fn free_waits_for_in_flight_calls_then_allows_reopen() {
+        let dir = TempDir::new().unwrap();
+        let h = create(&dir);
+        add(h, "a0");
+        commit(h);
+        // A call in flight holds its own reference, as every exported function does.
+        let in_flight = index(h).unwrap();
+        let call = thread::spawn(move || {
+            assert_eq!(in_flight.search(r#"{"type":"all"}"#).unwrap().total_count, 1);
+            thread::sleep(Duration::from_millis(100));
+        });
+        thread::sleep(Duration::from_millis(10));
+        let start = Instant::now();
+        tantivy_free_index(h);
+        assert!(start.elapsed() >= Duration::from_millis(50), "free returned while a call was in flight");
+        call.join().unwrap();
+        assert_eq!(tantivy_num_docs(h), 0, "freed handle still resolves");
+
+        // The writer lock is gone, so the path opens again right away.
+        let h = open(&dir);
+        assert_eq!(tantivy_num_docs(h), 1);
+        tantivy_free_index(h);
+        tantivy_free_index(h);
+    }
+
+    #[test]
+    fn free_wakes_opstamp_waiters() {
+        let dir = TempDir::new().unwrap();
+        let h = create(&dir);
+        add(h, "a0");
+        let waiter = thread::spawn(move || {
+            let mut err = ptr::null_mut();
+            let r = tantivy_wait_for_opstamp(h, 0, 60_000, &mut err);
+            (r, take(err))
+        });
+        thread::sleep(Duration::from_millis(20));
+        let start = Instant::now();
+        tantivy_free_index(h);
+        let (r, err) = waiter.join().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(r, ERR);
+        let e: Error = serde_json::from_str(&err.unwrap()).unwrap();
+        assert_eq!(e.message, "index closed");
+        tantivy_free_index(open(&dir));
+    }
+
+    #[test]
+    fn last_error_reports_failed_auto_commit() {
+        let dir = TempDir::new().unwrap();
+        let h = create(&dir);
+        assert_eq!(take(tantivy_last_error(h)), None);
+        let mut err = ptr::null_mut();
+        assert_eq!(tantivy_set_auto_commit(h, c(r#"{"interval_ms":10}"#).as_ptr(), &mut err), 0);
+        std::fs::remove_dir_all(dir.path()).unwrap();
+        assert!(tantivy_add_doc(h, c(r#"{"id":"a"}"#).as_ptr(), &mut err) >= 0);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut last = None;
+        while last.is_none() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+            last = take(tantivy_last_error(h));
+        }
+        let e: Error = serde_json::from_str(&last.expect("no background commit error")).unwrap();
+        assert_eq!((e.code, e.kind), (4, crate::ErrorKind::Io));
+        tantivy_free_index(h);
+    }
+
+    /// Copy a returned buffer out and free it.
+    fn take_buf(buf: TantivyBuf) -> Option<Vec<u8>> {
+        if buf.data.is_null() { return None; }
+        let v = unsafe { std::slice::from_raw_parts(buf.data, buf.len) }.to_vec();
+        tantivy_free_buf(buf);
+        Some(v)
+    }
+
+    #[test]
+    fn buf_calls_round_trip_bytes_and_nuls() {
+        const BLOBS: &str = r#"{"fields":[{"name":"id","type":"text","tokenizer":"raw"},{"name":"blob","type":"bytes","stored":true,"indexed":true}]}"#;
+        let dir = TempDir::new().unwrap();
+        let mut err = ptr::null_mut();
+        let h = tantivy_create_index(c(dir.path().to_str().unwrap()).as_ptr(), c(BLOBS).as_ptr(), &mut err);
+        assert_ne!(h, 0, "{:?}", take(err));
+        // Slices are read to `len`: no terminator needed, trailing bytes ignored.
+        let add = |doc: &str| {
+            let buf = format!("{}garbage", doc);
+            let mut err = ptr::null_mut();
+            assert!(tantivy_add_doc_buf(h, buf.as_ptr(), doc.len(), &mut err) >= 0, "{:?}", take(err));
+        };
+        add(r#"{"id":"a\u0000b","blob":"AAEA/w=="}"#);
+        add(r#"{"id":"c","blob":"AA=="}"#);
+        commit(h);
+
+        let search = |query: &str, encoding: u32| {
+            let mut err = ptr::null_mut();
+            let buf = tantivy_search_encoded(h, 0, query.as_ptr(), query.len(), encoding, &mut err);
+            take_buf(buf).ok_or_else(|| serde_json::from_str::<Error>(&take(err).unwrap()).unwrap())
+        };
+        let nul = r#"{"type":"term_match","field":"id","value":"a\u0000b"}"#;
+        let json: serde_json::Value = serde_json::from_slice(&search(nul, 0).unwrap()).unwrap();
+        let packed: serde_json::Value = rmp_serde::from_slice(&search(nul, 1).unwrap()).unwrap();
+        for v in [&json, &packed] {
+            assert_eq!(v["total_count"], 1);
+            assert_eq!(v["results"][0]["id"], "a\0b");
+            assert_eq!(v["results"][0]["blob"], "AAEA/w==");
+        }
+        let by_blob = search(r#"{"type":"term_match","field":"blob","value":"AAEA/w=="}"#, 0).unwrap();
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&by_blob).unwrap()["results"][0]["id"], "a\0b");
+
+        let mut err = ptr::null_mut();
+        let v = take_buf(tantivy_validate_query_buf(h, nul.as_ptr(), nul.len(), &mut err)).unwrap();
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&v).unwrap()["fields"], serde_json::json!(["id"]));
+
+        // Delete by the NUL-carrying id, with an unterminated field name.
+        let (field, value) = ("idXX", r#""a\u0000b""#);
+        let mut err = ptr::null_mut();
+        assert!(tantivy_delete_term_buf(h, field.as_ptr(), 2, value.as_ptr(), value.len(), &mut err) >= 0, "{:?}", take(err));
+        commit(h);
+        let all: serde_json::Value = serde_json::from_slice(&search(r#"{"type":"all"}"#, 0).unwrap()).unwrap();
+        assert_eq!(all["total_count"], 1);
+        assert_eq!(all["results"][0]["id"], "c");
+
+        // A null pointer with a length, and bytes that aren't UTF-8.
+        let mut err = ptr::null_mut();
+        assert!(take_buf(tantivy_search_encoded(h, 0, ptr::null(), 3, 0, &mut err)).is_none());
+        assert_eq!(serde_json::from_str::<Error>(&take(err).unwrap()).unwrap().kind, crate::ErrorKind::BadRequest);
+        let bad = [b'{', 0xff, b'}'];
+        let mut err = ptr::null_mut();
+        assert_eq!(tantivy_add_doc_buf(h, bad.as_ptr(), bad.len(), &mut err), ERR as i64);
+        assert_eq!(serde_json::from_str::<Error>(&take(err).unwrap()).unwrap().kind, crate::ErrorKind::BadRequest);
+        // Null with length 0 reads as empty input.
+        let mut err = ptr::null_mut();
+        assert!(take_buf(tantivy_validate_query_buf(h, ptr::null(), 0, &mut err)).is_none());
+        assert_eq!(serde_json::from_str::<Error>(&take(err).unwrap()).unwrap().kind, crate::ErrorKind::BadRequest);
+        tantivy_free_index(h);
     }
 }
@@ -3,19 +3,167 @@
 //! Schema-agnostic wrapper. Schema defined via JSON, documents added as JSON,
 //! queries expressed as a JSON DSL. Reusable for any data type.
 
+mod collapse;
 pub mod ffi;
+mod queries;
+mod registry;
+#[cfg(test)]
+mod testutil;
 
+use base64::Engine;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::path::Path;
-use std::sync::Mutex;
-use tantivy::collector::{Count, TopDocs};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tantivy::aggregation::agg_req::Aggregations;
+use tantivy::aggregation::agg_result::AggregationResults;
+use tantivy::aggregation::AggregationCollector;
+use tantivy::collector::{Collector, Count, SegmentCollector, TopDocs};
+use tantivy::columnar::{BytesColumn, Column, StrColumn};
 use tantivy::query::{
-    BooleanQuery, FuzzyTermQuery, Occur, PhraseQuery, Query, QueryParser,
-    RegexQuery, TermQuery,
+    BooleanQuery, BoostQuery, ConstScoreQuery, DisjunctionMaxQuery, EmptyQuery, EnableScoring, ExistsQuery, Explanation, FuzzyTermQuery,
+    MoreLikeThisQuery, Occur, PhraseQuery, Query, QueryParser, RangeQuery, RegexQuery, TermQuery, TermSetQuery, Weight,
 };
 use tantivy::schema::*;
-use tantivy::{Index, IndexReader, IndexWriter, ReloadPolicy, TantivyDocument};
+use tantivy::snippet::SnippetGenerator;
+use tantivy::tokenizer::TokenStream;
+use tantivy::{
+    DocAddress, Index, IndexReader, IndexWriter, Opstamp, Order, ReloadPolicy, Score, Searcher, SegmentId, SegmentReader, TantivyDocument,
+};
+
+use crate::collapse::{CollapseCollector, GroupSpec, InnerHits, InnerHitsCollector, Rank};
+use crate::queries::{Bm25TermQuery, MinShouldMatchQuery, ProximityQuery, SimilarQuery};
+
+// ========== Errors ==========
+
+/// Error category. The numeric codes are stable and part of the FFI contract.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    BadRequest = 1,
+    NotFound = 2,
+    SchemaMismatch = 3,
+    Io = 4,
+    LockBusy = 5,
+    Internal = 6,
+    /// A panic caught at the FFI boundary.
+    Panic = 7,
+}
+
+/// Structured error, serialized over FFI as `{code, kind, message, path}`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Error {
+    pub code: u32,
+    pub kind: ErrorKind,
+    pub message: String,
+    /// Location of the offending input (e.g. a JSON path), when known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Error { code: kind as u32, kind, message: message.into(), path: None }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self { Self::new(ErrorKind::BadRequest, message) }
+    pub fn not_found(message: impl Into<String>) -> Self { Self::new(ErrorKind::NotFound, message) }
+    pub fn schema_mismatch(message: impl Into<String>) -> Self { Self::new(ErrorKind::SchemaMismatch, message) }
+    pub fn internal(message: impl Into<String>) -> Self { Self::new(ErrorKind::Internal, message) }
+    pub fn panic(message: impl Into<String>) -> Self { Self::new(ErrorKind::Panic, message) }
+
+    pub fn with_path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Prefix the path with the location of an enclosing JSON node.
+    pub fn under(mut self, prefix: &str) -> Self {
+        if prefix.is_empty() { return self; }
+        self.path = Some(match self.path.take() {
+            Some(p) => format!("{}.{}", prefix, p),
+            None => prefix.to_string(),
+        });
+        self
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_else(|_| self.message.clone())
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.path {
+            Some(p) => write!(f, "{} (at {})", self.message, p),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl From<tantivy::TantivyError> for Error {
+    fn from(e: tantivy::TantivyError) -> Self {
+        use tantivy::directory::error::{OpenDirectoryError, OpenReadError};
+        use tantivy::TantivyError as TE;
+        let kind = match &e {
+            TE::OpenDirectoryError(OpenDirectoryError::DoesNotExist(_))
+            | TE::OpenReadError(OpenReadError::FileDoesNotExist(_))
+            | TE::FieldNotFound(_) => ErrorKind::NotFound,
+            TE::OpenDirectoryError(_) | TE::OpenReadError(_) | TE::OpenWriteError(_)
+            | TE::IoError(_) | TE::DataCorruption(_) => ErrorKind::Io,
+            TE::LockFailure(..) => ErrorKind::LockBusy,
+            TE::SchemaError(_) | TE::IncompatibleIndex(_) => ErrorKind::SchemaMismatch,
+            TE::InvalidArgument(_) | TE::AggregationError(_) | TE::IndexAlreadyExists => ErrorKind::BadRequest,
+            _ => ErrorKind::Internal,
+        };
+        Error::new(kind, e.to_string())
+    }
+}
+
+impl From<tantivy::query::QueryParserError> for Error {
+    fn from(e: tantivy::query::QueryParserError) -> Self { Error::bad_request(e.to_string()) }
+}
+
+impl From<rmp_serde::encode::Error> for Error {
+    fn from(e: rmp_serde::encode::Error) -> Self { Error::internal(format!("msgpack: {}", e)) }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self { Error::bad_request(e.to_string()) }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        let kind = match e.kind() {
+            std::io::ErrorKind::NotFound => ErrorKind::NotFound,
+            _ => ErrorKind::Io,
+        };
+        Error::new(kind, e.to_string())
+    }
+}
+
+impl<T> From<std::sync::PoisonError<T>> for Error {
+    fn from(e: std::sync::PoisonError<T>) -> Self { Error::internal(e.to_string()) }
+}
+
+/// Prefix an error's message with what was being done, keeping its kind.
+trait Context<T> {
+    fn ctx(self, what: &str) -> Result<T, Error>;
+}
+
+impl<T, E: Into<Error>> Context<T> for Result<T, E> {
+    fn ctx(self, what: &str) -> Result<T, Error> {
+        self.map_err(|e| {
+            let mut e = e.into();
+            e.message = format!("{}: {}", what, e.message);
+            e
+        })
+    }
+}
 
 // ========== Schema Definition ==========
 
@@ -23,7 +171,7 @@ use tantivy::{Index, IndexReader, IndexWriter, ReloadPolicy, TantivyDocument};
 pub struct FieldDef {
     pub name: String,
     #[serde(rename = "type")]
-    pub field_type: String,   // "text", "i64", "f64"
+    pub field_type: String,   // "text", "i64", "f64", "bytes" (base64 in JSON)
     #[serde(default = "yes")]
     pub stored: bool,
     #[serde(default = "yes")]
@@ -32,20 +180,85 @@ pub struct FieldDef {
     pub fast: bool,
     #[serde(default = "default_tok")]
     pub tokenizer: String,    // "default", "raw", "en_stem"
+    /// BM25 settings for this field's term scores; requests can override them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bm25: Option<Bm25Def>,
 }
 
 fn yes() -> bool { true }
 fn default_tok() -> String { "default".to_string() }
 
+/// Tantivy's built-in BM25 parameters.
+const BM25_K1: Score = 1.2;
+const BM25_B: Score = 0.75;
+
+/// BM25 parameters; unset values fall back to the next level (request,
+/// schema field, then tantivy's k1 1.2 and b 0.75).
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Bm25Def {
+    /// Term frequency saturation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub k1: Option<Score>,
+    /// Length normalization, 0 (none) to 1 (full).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub b: Option<Score>,
+}
+
+impl Bm25Def {
+    fn check(&self, path: &str) -> Result<(), Error> {
+        if let Some(k1) = self.k1 {
+            if !k1.is_finite() || k1 < 0.0 { return Err(Error::bad_request(format!("invalid k1: {}", k1)).with_path(format!("{}.k1", path))); }
+        }
+        if let Some(b) = self.b {
+            if !(0.0..=1.0).contains(&b) { return Err(Error::bad_request(format!("invalid b: {}", b)).with_path(format!("{}.b", path))); }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SchemaDef {
     pub fields: Vec<FieldDef>,
+    /// Default fields for text-like queries; `"title^3"` weights a field.
     #[serde(default)]
     pub search_fields: Vec<String>,
 }
 
+/// Runtime options for opening or creating an index.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IndexOptions {
+    #[serde(default = "default_reload")]
+    pub reload_policy: String,  // "on_commit", "manual"
+    /// Background commits from the start; same as a later `set_auto_commit`.
+    #[serde(default)]
+    pub auto_commit: Option<AutoCommitConfig>,
+}
+
+fn default_reload() -> String { "on_commit".to_string() }
+
+impl Default for IndexOptions {
+    fn default() -> Self { IndexOptions { reload_policy: default_reload(), auto_commit: None } }
+}
+
+impl IndexOptions {
+    fn parse(json: &str) -> Result<Self, Error> {
+        if json.trim().is_empty() { return Ok(Self::default()); }
+        serde_json::from_str(json).ctx("options")
+    }
+}
+
 // ========== Query DSL ==========
 
+/// A node of the query tree: a `QueryDef` plus properties every query type accepts.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct QueryNode {
+    #[serde(flatten)]
+    pub def: QueryDef,
+    /// Score multiplier for this clause.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub boost: Option<Score>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
 pub enum QueryDef {
@@ -54,10 +267,16 @@ pub enum QueryDef {
         query: String,
         #[serde(default)]
         fields: Vec<String>,
-        #[serde(default = "default_limit")]
-        limit: usize,
+        /// Operator between bare terms: "or" (default) or "and".
+        #[serde(default = "default_operator")]
+        default_operator: String,
+        /// Recover from syntax errors instead of failing; what was dropped is
+        /// reported in the results' `warnings`.
+        #[serde(default)]
+        lenient: bool,
+        /// Per-field fuzzy matching for every term of the query.
         #[serde(default)]
-        offset: usize,
+        fuzzy: BTreeMap<String, FuzzyDef>,
     },
     #[serde(rename = "fuzzy")]
     Fuzzy {
@@ -66,39 +285,43 @@ pub enum QueryDef {
         distance: u8,
         #[serde(default)]
         fields: Vec<String>,
-        #[serde(default = "default_limit")]
-        limit: usize,
-        #[serde(default)]
-        offset: usize,
     },
     #[serde(rename = "phrase")]
     Phrase {
         phrase: String,
         #[serde(default)]
         fields: Vec<String>,
-        #[serde(default = "default_limit")]
-        limit: usize,
+        /// Extra words allowed between the phrase terms, in total.
+        #[serde(default)]
+        slop: u32,
+    },
+    /// All words of `query` within `distance` words of each other.
+    #[serde(rename = "proximity")]
+    Proximity {
+        query: String,
+        distance: u32,
+        /// Require the words in query order.
         #[serde(default)]
-        offset: usize,
+        ordered: bool,
+        #[serde(default)]
+        fields: Vec<String>,
     },
     #[serde(rename = "prefix")]
     Prefix {
         prefix: String,
         #[serde(default)]
         fields: Vec<String>,
-        #[serde(default = "default_limit")]
-        limit: usize,
-        #[serde(default)]
-        offset: usize,
     },
     #[serde(rename = "term_match")]
     TermMatch {
         field: String,
         value: serde_json::Value,
-        #[serde(default = "default_limit")]
-        limit: usize,
-        #[serde(default)]
-        offset: usize,
+    },
+    /// Exact match against any of `values`; scales to large ID lists.
+    #[serde(rename = "terms")]
+    Terms {
+        field: String,
+        values: Vec<serde_json::Value>,
     },
     #[serde(rename = "range_i64")]
     RangeI64 {
@@ -107,10 +330,6 @@ pub enum QueryDef {
         min: Option<i64>,
         #[serde(default)]
         max: Option<i64>,
-        #[serde(default = "default_limit")]
-        limit: usize,
-        #[serde(default)]
-        offset: usize,
     },
     #[serde(rename = "range_f64")]
     RangeF64 {
@@ -119,371 +338,3039 @@ pub enum QueryDef {
         min: Option<f64>,
         #[serde(default)]
         max: Option<f64>,
-        #[serde(default = "default_limit")]
-        limit: usize,
-        #[serde(default)]
-        offset: usize,
     },
     #[serde(rename = "bool")]
     Bool {
         #[serde(default)]
-        must: Vec<QueryDef>,
+        must: Vec<QueryNode>,
         #[serde(default)]
-        should: Vec<QueryDef>,
+        should: Vec<QueryNode>,
         #[serde(default)]
-        must_not: Vec<QueryDef>,
-        #[serde(default = "default_limit")]
-        limit: usize,
+        must_not: Vec<QueryNode>,
+        /// How many `should` clauses must match: a count (`2`), a percentage
+        /// (`"75%"`), or a negative form meaning "all but" (`-1`, `"-25%"`).
         #[serde(default)]
-        offset: usize,
+        minimum_should_match: Option<MinimumShouldMatch>,
     },
-    #[serde(rename = "all")]
-    All {
-        #[serde(default = "default_limit")]
-        limit: usize,
+    /// Best-matching sub-query wins; the others add `tie_breaker` (0 to 1)
+    /// times their score.
+    #[serde(rename = "dis_max")]
+    DisMax {
+        queries: Vec<QueryNode>,
+        #[serde(default)]
+        tie_breaker: Score,
+    },
+    /// Terms matching a regular expression, in text `fields`.
+    #[serde(rename = "regex")]
+    Regex {
+        pattern: String,
+        #[serde(default)]
+        fields: Vec<String>,
+        /// Match regardless of case. Defaults to true unless a field is `raw`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        case_insensitive: Option<bool>,
+    },
+    /// Terms matching a glob: `*` is any run of characters, `?` one
+    /// character, `\` escapes the next character.
+    #[serde(rename = "wildcard")]
+    Wildcard {
+        pattern: String,
+        #[serde(default)]
+        fields: Vec<String>,
+        /// As for `regex`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        case_insensitive: Option<bool>,
+    },
+    /// Documents with at least one value in `field`. Negate it with
+    /// `must_not` to find documents missing the field.
+    #[serde(rename = "exists")]
+    Exists {
+        field: String,
+    },
+    /// Blend the score of `query` with functions of fast-field values. Only
+    /// valid as the root of a search request.
+    #[serde(rename = "function_score")]
+    FunctionScore {
+        query: Box<QueryNode>,
+        functions: Vec<ScoreFunction>,
+        /// How function values combine: "multiply" (default), "sum", "avg", "max" or "min".
+        #[serde(default = "default_score_mode")]
+        score_mode: String,
+        /// How that combines with the query score: "multiply" (default), "sum" or "replace".
+        #[serde(default = "default_score_mode")]
+        boost_mode: String,
+    },
+    /// Documents similar to `like_doc`, the document whose `id_field` holds
+    /// that value, or to `like_text`, judged by their most significant terms.
+    #[serde(rename = "more_like_this")]
+    MoreLikeThis {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        like_doc: Option<serde_json::Value>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        like_text: Option<String>,
+        #[serde(default = "default_id_field")]
+        id_field: String,
         #[serde(default)]
-        offset: usize,
+        fields: Vec<String>,
+        /// Ignore terms found in fewer documents than this.
+        #[serde(default = "default_min_doc_frequency")]
+        min_doc_frequency: u64,
+        /// Ignore terms occurring fewer times than this in the source.
+        #[serde(default = "default_min_term_frequency")]
+        min_term_frequency: usize,
+        /// Keep at most this many terms, the most significant first.
+        #[serde(default = "default_max_query_terms")]
+        max_query_terms: usize,
+        /// Let the `like_doc` document match itself.
+        #[serde(default)]
+        include: bool,
     },
+    /// Matches what `query` matches, every hit scoring `score`.
+    #[serde(rename = "constant_score")]
+    ConstantScore {
+        query: Box<QueryNode>,
+        #[serde(default = "default_const_score")]
+        score: Score,
+    },
+    #[serde(rename = "all")]
+    All,
 }
 
-fn default_limit() -> usize { 100 }
-fn default_dist() -> u8 { 2 }
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FuzzyDef {
+    #[serde(default = "default_fuzzy_dist")]
+    pub distance: u8,
+    /// Also match terms that merely start with a fuzzy match.
+    #[serde(default)]
+    pub prefix: bool,
+    /// Count a transposition as one edit instead of two.
+    #[serde(default = "yes")]
+    pub transpositions: bool,
+}
 
-impl QueryDef {
-    fn limit(&self) -> usize {
-        match self {
-            QueryDef::Text { limit, .. } => *limit,
-            QueryDef::Fuzzy { limit, .. } => *limit,
-            QueryDef::Phrase { limit, .. } => *limit,
-            QueryDef::Prefix { limit, .. } => *limit,
-            QueryDef::TermMatch { limit, .. } => *limit,
-            QueryDef::RangeI64 { limit, .. } => *limit,
-            QueryDef::RangeF64 { limit, .. } => *limit,
-            QueryDef::Bool { limit, .. } => *limit,
-            QueryDef::All { limit, .. } => *limit,
+/// A `function_score` function over an i64/f64 fast field. Documents without
+/// a value are left out of the combination.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScoreFunction {
+    #[serde(flatten)]
+    pub kind: FunctionKind,
+    /// Multiplier for the function's value.
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum FunctionKind {
+    /// `modifier(factor * value)`, e.g. `log1p` for citation counts.
+    FieldValueFactor {
+        field: String,
+        #[serde(default = "default_factor")]
+        factor: f64,
+        /// "none", "log", "log1p", "log2p", "ln", "ln1p", "ln2p", "square", "sqrt" or "reciprocal".
+        #[serde(default = "default_modifier")]
+        modifier: String,
+        /// Value for documents without one.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        missing: Option<f64>,
+    },
+    Gauss(DecayDef),
+    Exp(DecayDef),
+    Linear(DecayDef),
+}
+
+/// Decay by distance from `origin`: 1 within `offset`, `decay` at
+/// `offset + scale`, e.g. recency in days around today's date.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DecayDef {
+    pub field: String,
+    pub origin: f64,
+    pub scale: f64,
+    #[serde(default)]
+    pub offset: f64,
+    #[serde(default = "default_decay")]
+    pub decay: f64,
+}
+
+impl ScoreFunction {
+    fn field(&self) -> &str {
+        match &self.kind {
+            FunctionKind::FieldValueFactor { field, .. } => field,
+            FunctionKind::Gauss(d) | FunctionKind::Exp(d) | FunctionKind::Linear(d) => &d.field,
         }
     }
 
-    fn offset(&self) -> usize {
-        match self {
-            QueryDef::Text { offset, .. } => *offset,
-            QueryDef::Fuzzy { offset, .. } => *offset,
-            QueryDef::Phrase { offset, .. } => *offset,
-            QueryDef::Prefix { offset, .. } => *offset,
-            QueryDef::TermMatch { offset, .. } => *offset,
-            QueryDef::RangeI64 { offset, .. } => *offset,
-            QueryDef::RangeF64 { offset, .. } => *offset,
-            QueryDef::Bool { offset, .. } => *offset,
-            QueryDef::All { offset, .. } => *offset,
+    fn name(&self) -> &'static str {
+        match &self.kind {
+            FunctionKind::FieldValueFactor { .. } => "field_value_factor",
+            FunctionKind::Gauss(_) => "gauss",
+            FunctionKind::Exp(_) => "exp",
+            FunctionKind::Linear(_) => "linear",
         }
     }
+
+    /// Validate against the schema; paths are relative to the function.
+    fn check(&self, fm: &FieldMap) -> Result<(), Error> {
+        let at = |key: &str| format!("{}.{}", self.name(), key);
+        match fm.get(self.field()) {
+            Some((_, fd)) if fd.fast && (fd.field_type == "i64" || fd.field_type == "f64") => {}
+            Some(_) => return Err(Error::schema_mismatch(format!("field is not a fast i64/f64 field: {}", self.field())).with_path(at("field"))),
+            None => return Err(Error::schema_mismatch(format!("unknown field: {}", self.field())).with_path(at("field"))),
+        }
+        // `weight` sits beside the function kind, not inside it like the keys `at` covers.
+        if !self.weight.is_finite() || self.weight < 0.0 {
+            return Err(Error::bad_request(format!("invalid weight: {}", self.weight)).with_path("weight"));
+        }
+        match &self.kind {
+            FunctionKind::FieldValueFactor { modifier, .. } => match modifier.as_str() {
+                "none" | "log" | "log1p" | "log2p" | "ln" | "ln1p" | "ln2p" | "square" | "sqrt" | "reciprocal" => Ok(()),
+                other => Err(Error::bad_request(format!("unknown modifier: {}", other)).with_path(at("modifier"))),
+            },
+            FunctionKind::Gauss(d) | FunctionKind::Exp(d) | FunctionKind::Linear(d) => {
+                if !d.scale.is_finite() || d.scale <= 0.0 { return Err(Error::bad_request(format!("invalid scale: {}", d.scale)).with_path(at("scale"))); }
+                if !d.offset.is_finite() || d.offset < 0.0 { return Err(Error::bad_request(format!("invalid offset: {}", d.offset)).with_path(at("offset"))); }
+                if d.decay.is_nan() || d.decay <= 0.0 || d.decay >= 1.0 { return Err(Error::bad_request(format!("invalid decay: {}", d.decay)).with_path(at("decay"))); }
+                Ok(())
+            }
+        }
+    }
+
+    /// Weighted value for a document holding `value`; `None` when it has none.
+    fn eval(&self, value: Option<f64>) -> Option<f64> {
+        let x = match &self.kind {
+            FunctionKind::FieldValueFactor { factor, modifier, missing, .. } => {
+                let x = factor * value.or(*missing)?;
+                match modifier.as_str() {
+                    "log" => x.log10(),
+                    "log1p" => (x + 1.0).log10(),
+                    "log2p" => (x + 2.0).log10(),
+                    "ln" => x.ln(),
+                    "ln1p" => x.ln_1p(),
+                    "ln2p" => (x + 2.0).ln(),
+                    "square" => x * x,
+                    "sqrt" => x.sqrt(),
+                    "reciprocal" => 1.0 / x,
+                    _ => x,
+                }
+            }
+            FunctionKind::Gauss(d) => {
+                let dist = d.distance(value?);
+                let sigma2 = -d.scale * d.scale / (2.0 * d.decay.ln());
+                (-dist * dist / (2.0 * sigma2)).exp()
+            }
+            FunctionKind::Exp(d) => (d.decay.ln() / d.scale * d.distance(value?)).exp(),
+            FunctionKind::Linear(d) => {
+                let s = d.scale / (1.0 - d.decay);
+                ((s - d.distance(value?)) / s).max(0.0)
+            }
+        };
+        // Modifiers can leave the domain (log of 0, sqrt of a negative); such
+        // values count as 0 rather than failing the whole search.
+        Some(if x.is_finite() { self.weight * x } else { 0.0 })
+    }
 }
 
-// ========== Results ==========
+impl DecayDef {
+    fn distance(&self, value: f64) -> f64 {
+        ((value - self.origin).abs() - self.offset).max(0.0)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum MinimumShouldMatch {
+    Count(i64),
+    Spec(String),
+}
+
+impl MinimumShouldMatch {
+    /// Number of `should` clauses required out of `n`.
+    fn resolve(&self, n: usize) -> Result<usize, Error> {
+        let bad = || Error::bad_request(format!("invalid minimum_should_match: {}", serde_json::json!(self))).with_path("minimum_should_match");
+        let (neg, count) = match self {
+            MinimumShouldMatch::Count(c) => (*c < 0, c.unsigned_abs() as usize),
+            MinimumShouldMatch::Spec(s) => {
+                let s = s.trim();
+                let (neg, s) = match s.strip_prefix('-') { Some(r) => (true, r), None => (false, s) };
+                match s.strip_suffix('%') {
+                    Some(p) => {
+                        let p: f64 = p.trim().parse().map_err(|_| bad())?;
+                        if !(0.0..=100.0).contains(&p) { return Err(bad()); }
+                        (neg, (n as f64 * p / 100.0).floor() as usize)
+                    }
+                    None => (neg, s.parse().map_err(|_| bad())?),
+                }
+            }
+        };
+        Ok(if neg { n.saturating_sub(count) } else { count })
+    }
+}
 
+/// A search request: a pure query tree plus paging, sorting, projection,
+/// highlighting and aggregations.
+///
+/// The legacy flat form (`{"type":"text","query":"...","limit":10}`) is still
+/// accepted; see [`SearchRequest::parse`].
 #[derive(Serialize, Deserialize, Debug)]
-pub struct SearchResults {
-    pub results: Vec<serde_json::Value>,
-    pub count: usize,
-    pub total_count: usize,
+pub struct SearchRequest {
+    pub query: QueryNode,
+    #[serde(default = "default_limit")]
     pub limit: usize,
+    #[serde(default)]
     pub offset: usize,
+    /// Order by a fast field instead of by score.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sort: Option<SortDef>,
+    /// Stored fields to return (empty = all, unless `docvalue_fields` is set).
+    /// `return_fields`, the flat form's name, is accepted as an alias.
+    #[serde(default, alias = "return_fields")]
+    pub fields: Vec<String>,
+    /// Stored fields to leave out.
+    #[serde(default)]
+    pub exclude_fields: Vec<String>,
+    /// Fast fields read from columnar storage. When set without `fields`,
+    /// the doc store is not touched at all.
+    #[serde(default)]
+    pub docvalue_fields: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub highlight: Option<HighlightDef>,
+    /// Tantivy (Elasticsearch-compatible) aggregation request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aggs: Option<serde_json::Value>,
+    /// Attach tantivy's score explanation tree to each hit as `_explanation`.
+    #[serde(default)]
+    pub explain: bool,
+    /// "bm25" (default) or "none": skip scoring entirely for pure filters.
+    /// Without `sort`, hits then come back in no particular order.
+    #[serde(default = "default_scoring")]
+    pub scoring: String,
+    /// BM25 `k1`/`b` for this request, overriding the schema's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bm25: Option<Bm25Request>,
+    /// Drop hits scoring below this before they are counted or ranked. With
+    /// `sort` it still applies to the relevance score.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_score: Option<Score>,
+    /// Only count matches: no hits or doc loading. Rejected with `aggs`,
+    /// `collapse`, `highlight` or `explain`.
+    #[serde(default)]
+    pub count_only: bool,
+    /// Return only the best hit per distinct value of a fast field. Counting
+    /// `total_groups` keeps every matching group key in memory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collapse: Option<CollapseDef>,
+    /// Parsed from the flat form, where the query tree is the root object.
+    #[serde(skip)]
+    flat: bool,
+    /// Request options found on nested query nodes, which ignore them.
+    #[serde(skip)]
+    warnings: Vec<String>,
 }
 
-// ========== Index ==========
+/// Request-level BM25 settings: `k1`/`b` for every field, `fields` per field.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Bm25Request {
+    #[serde(flatten)]
+    pub all: Bm25Def,
+    #[serde(default)]
+    pub fields: BTreeMap<String, Bm25Def>,
+}
 
-pub struct TantivyIndex {
-    index: Index,
-    reader: IndexReader,
-    writer: Mutex<IndexWriter>,
-    #[allow(dead_code)]
-    schema: Schema,
-    field_map: HashMap<String, (Field, FieldDef)>,
-    search_fields: Vec<Field>,
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SortDef {
+    pub field: String,
+    #[serde(default = "default_order")]
+    pub order: String,   // "asc", "desc"
 }
 
-impl TantivyIndex {
-    pub fn create(path: &str, schema_json: &str) -> Result<Self, String> {
-        let _ = std::fs::remove_dir_all(path);
-        std::fs::create_dir_all(path).map_err(|e| format!("mkdir: {}", e))?;
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CollapseDef {
+    pub field: String,
+    /// Best hits of each group to return in the top hit's `_inner_hits`.
+    #[serde(default)]
+    pub inner_hits: usize,
+}
 
-        let schema_def: SchemaDef =
-            serde_json::from_str(schema_json).map_err(|e| format!("schema: {}", e))?;
-        let (schema, fmap) = Self::build_schema(&schema_def)?;
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HighlightDef {
+    pub fields: Vec<String>,
+    #[serde(default = "default_fragment")]
+    pub max_chars: usize,
+}
 
-        std::fs::write(Path::new(path).join("_schema.json"), schema_json)
-            .map_err(|e| format!("save schema: {}", e))?;
+/// Request options of the flat form, read alongside `type`.
+#[derive(Deserialize)]
+struct FlatOptions {
+    #[serde(default = "default_limit")]
+    limit: usize,
+    #[serde(default)]
+    offset: usize,
+    #[serde(default)]
+    return_fields: Vec<String>,
+    #[serde(default)]
+    exclude_fields: Vec<String>,
+    #[serde(default)]
+    docvalue_fields: Vec<String>,
+    #[serde(default)]
+    min_score: Option<Score>,
+    #[serde(default)]
+    count_only: bool,
+}
 
-        let index =
-            Index::create_in_dir(Path::new(path), schema.clone()).map_err(|e| e.to_string())?;
-        let sf = Self::resolve_search_fields(&schema_def, &fmap);
-        Self::finish(index, schema, fmap, sf)
+impl SearchRequest {
+    /// Parse either an envelope (`{"query":{...},"limit":10}`) or the flat
+    /// form, where the query tree and its options share one object.
+    pub fn parse(json: &str) -> Result<Self, Error> {
+        let v: serde_json::Value = serde_json::from_str(json).ctx("query")?;
+        if v.get("type").is_none() && v.get("query").is_some_and(|q| q.is_object()) {
+            let req: Result<Self, _> = serde_json::from_value(v.clone());
+            let mut req = req.ctx("query").map_err(|e| locate_parse_error(&v["query"], "query").unwrap_or(e))?;
+            nested_option_warnings(&v["query"], "query", true, &mut req.warnings);
+            return Ok(req);
+        }
+        let query: Result<QueryNode, _> = serde_json::from_value(v.clone());
+        let query = query.ctx("query").map_err(|e| locate_parse_error(&v, "").unwrap_or(e))?;
+        let mut warnings = Vec::new();
+        nested_option_warnings(&v, "", false, &mut warnings);
+        let o: FlatOptions = serde_json::from_value(v).ctx("query")?;
+        Ok(SearchRequest {
+            query, limit: o.limit, offset: o.offset, sort: None,
+            fields: o.return_fields, exclude_fields: o.exclude_fields, docvalue_fields: o.docvalue_fields,
+            highlight: None, aggs: None, explain: false, scoring: default_scoring(), bm25: None,
+            min_score: o.min_score, count_only: o.count_only, collapse: None, flat: true, warnings,
+        })
     }
 
-    pub fn open(path: &str) -> Result<Self, String> {
-        let sj = std::fs::read_to_string(Path::new(path).join("_schema.json"))
-            .map_err(|e| format!("read schema: {}", e))?;
-        let schema_def: SchemaDef =
-            serde_json::from_str(&sj).map_err(|e| format!("schema: {}", e))?;
-        let (schema, fmap) = Self::build_schema(&schema_def)?;
-        let index = Index::open_in_dir(Path::new(path)).map_err(|e| e.to_string())?;
-        let sf = Self::resolve_search_fields(&schema_def, &fmap);
-        Self::finish(index, schema, fmap, sf)
+    /// JSON path of the query tree's root, used to prefix query error paths.
+    fn query_path(&self) -> &'static str {
+        if self.flat { "" } else { "query" }
     }
+}
 
-    fn finish(index: Index, schema: Schema, fmap: HashMap<String, (Field, FieldDef)>, sf: Vec<Field>) -> Result<Self, String> {
-        let reader = index.reader_builder()
-            .reload_policy(ReloadPolicy::OnCommitWithDelay)
-            .try_into().map_err(|e| format!("reader: {}", e))?;
-        let writer = index.writer(256_000_000).map_err(|e| format!("writer: {}", e))?;
-        Ok(TantivyIndex { index, reader, writer: Mutex::new(writer), schema, field_map: fmap, search_fields: sf })
+/// Find the innermost query node that fails to parse, so errors can point at
+/// paths like `must[2].field` instead of a line and column.
+fn locate_parse_error(v: &serde_json::Value, path: &str) -> Option<Error> {
+    let err = serde_json::from_value::<QueryNode>(v.clone()).err()?;
+    if let Some(child) = v.get("query").filter(|q| q.is_object()) {
+        if let Some(e) = locate_parse_error(child, "query") { return Some(e.under(path)); }
     }
-
-    fn build_schema(def: &SchemaDef) -> Result<(Schema, HashMap<String, (Field, FieldDef)>), String> {
-        let mut sb = Schema::builder();
-        let mut fm = HashMap::new();
-        for fd in &def.fields {
-            let field = match fd.field_type.as_str() {
-                "text" => {
-                    let mut o = TextOptions::default();
-                    if fd.stored { o = o.set_stored(); }
-                    if fd.indexed {
-                        let tok = match fd.tokenizer.as_str() { "raw" => "raw", "en_stem" => "en_stem", _ => "default" };
-                        let rec = if fd.tokenizer == "raw" { IndexRecordOption::Basic } else { IndexRecordOption::WithFreqsAndPositions };
-                        o = o.set_indexing_options(TextFieldIndexing::default().set_tokenizer(tok).set_index_option(rec));
-                    }
-                    if fd.fast { o = o.set_fast(None); }
-                    sb.add_text_field(&fd.name, o)
-                }
-                "i64" => {
-                    let mut o = NumericOptions::default();
-                    if fd.stored { o = o.set_stored(); }
-                    if fd.indexed { o = o.set_indexed(); }
-                    if fd.fast { o = o.set_fast(); }
-                    sb.add_i64_field(&fd.name, o)
-                }
-                "f64" => {
-                    let mut o = NumericOptions::default();
-                    if fd.stored { o = o.set_stored(); }
-                    if fd.indexed { o = o.set_indexed(); }
-                    if fd.fast { o = o.set_fast(); }
-                    sb.add_f64_field(&fd.name, o)
-                }
-                t => return Err(format!("unknown type: {}", t)),
-            };
-            fm.insert(fd.name.clone(), (field, fd.clone()));
+    for key in ["must", "should", "must_not", "queries"] {
+        let Some(children) = v.get(key).and_then(|c| c.as_array()) else { continue };
+        for (i, child) in children.iter().enumerate() {
+            if let Some(e) = locate_parse_error(child, &format!("{}[{}]", key, i)) { return Some(e.under(path)); }
         }
-        Ok((sb.build(), fm))
     }
+    // This node itself is malformed; name the missing field when serde reports one.
+    let msg = err.to_string();
+    let e = Error::bad_request(format!("query: {}", msg));
+    Some(match msg.strip_prefix("missing field `").and_then(|r| r.split('`').next()) {
+        Some(field) => e.with_path(field).under(path),
+        None if path.is_empty() => e,
+        None => e.with_path(path),
+    })
+}
 
-    fn resolve_search_fields(def: &SchemaDef, fm: &HashMap<String, (Field, FieldDef)>) -> Vec<Field> {
-        if !def.search_fields.is_empty() {
-            def.search_fields.iter().filter_map(|n| fm.get(n).map(|(f, _)| *f)).collect()
-        } else {
-            fm.values().filter(|(_, d)| d.field_type == "text" && d.indexed && d.tokenizer != "raw").map(|(f, _)| *f).collect()
+/// Request options of the flat form, which only apply at the root.
+const FLAT_OPTIONS: [&str; 7] = ["limit", "offset", "return_fields", "exclude_fields", "docvalue_fields", "min_score", "count_only"];
+
+/// Warn about flat-form request options on query nodes where they are
+/// ignored: every node of an envelope's query tree, and every nested node.
+fn nested_option_warnings(v: &serde_json::Value, path: &str, check: bool, out: &mut Vec<String>) {
+    let at = |key: &str| if path.is_empty() { key.to_string() } else { format!("{}.{}", path, key) };
+    if check {
+        for key in FLAT_OPTIONS.iter().filter(|k| v.get(**k).is_some()) {
+            out.push(format!("{}: ignored, request options only apply at the root of the request", at(key)));
         }
     }
-
-    // ===== Document Operations =====
-
-    pub fn add_doc(&self, doc_json: &str) -> Result<(), String> {
-        let map: HashMap<String, serde_json::Value> =
-            serde_json::from_str(doc_json).map_err(|e| format!("doc: {}", e))?;
-        let mut doc = TantivyDocument::new();
-        for (name, val) in &map {
-            if let Some((field, fd)) = self.field_map.get(name) {
-                match fd.field_type.as_str() {
-                    "text" => { if let Some(s) = val.as_str() { doc.add_text(*field, s); } }
-                    "i64" => {
-                        if let Some(n) = val.as_i64() { doc.add_i64(*field, n); }
-                        else if let Some(n) = val.as_f64() { doc.add_i64(*field, n as i64); }
-                    }
-                    "f64" => { if let Some(n) = val.as_f64() { doc.add_f64(*field, n); } }
-                    _ => {}
-                }
-            }
+    if let Some(child) = v.get("query").filter(|q| q.is_object()) {
+        nested_option_warnings(child, &at("query"), true, out);
+    }
+    for key in ["must", "should", "must_not", "queries"] {
+        let Some(children) = v.get(key).and_then(|c| c.as_array()) else { continue };
+        for (i, child) in children.iter().enumerate() {
+            nested_option_warnings(child, &at(&format!("{}[{}]", key, i)), true, out);
         }
-        let w = self.writer.lock().map_err(|e| e.to_string())?;
-        w.add_document(doc).map_err(|e| e.to_string())?;
-        Ok(())
     }
+}
 
-    pub fn commit(&self) -> Result<(), String> {
-        let mut w = self.writer.lock().map_err(|e| e.to_string())?;
-        w.commit().map_err(|e| e.to_string())?;
-        self.reader.reload().map_err(|e| e.to_string())?;
-        Ok(())
-    }
+/// A parsed request with its built tantivy query.
+struct Prepared {
+    req: SearchRequest,
+    query: Box<dyn Query>,
+    warnings: Vec<String>,
+}
 
-    pub fn num_docs(&self) -> u64 { self.reader.searcher().num_docs() }
+/// Per-request state threaded through query building.
+struct BuildContext {
+    /// The searcher the query will run on, for queries built from its documents.
+    searcher: Searcher,
+    /// BM25 `(k1, b)` of fields that differ from tantivy's defaults.
+    bm25: HashMap<Field, (Score, Score)>,
+    /// Problems recovered from while parsing lenient text queries.
+    warnings: Vec<String>,
+}
 
-    // ===== Search =====
+fn default_limit() -> usize { 100 }
+fn default_dist() -> u8 { 2 }
+fn default_fuzzy_dist() -> u8 { 1 }
+fn default_operator() -> String { "or".to_string() }
+fn default_order() -> String { "desc".to_string() }
+fn default_fragment() -> usize { 150 }
+fn default_id_field() -> String { "id".to_string() }
+fn default_min_doc_frequency() -> u64 { 5 }
+fn default_min_term_frequency() -> usize { 2 }
+fn default_max_query_terms() -> usize { 25 }
+fn default_scoring() -> String { "bm25".to_string() }
+fn default_score_mode() -> String { "multiply".to_string() }
+fn default_weight() -> f64 { 1.0 }
+fn default_factor() -> f64 { 1.0 }
+fn default_modifier() -> String { "none".to_string() }
+fn default_decay() -> f64 { 0.5 }
+fn default_const_score() -> Score { 1.0 }
 
-    pub fn search(&self, query_json: &str) -> Result<SearchResults, String> {
-        let qd: QueryDef = serde_json::from_str(query_json).map_err(|e| format!("query: {}", e))?;
-        let limit = qd.limit();
-        let offset = qd.offset();
-        let query = self.build_query(&qd)?;
-        self.exec(query, limit, offset)
-    }
+// ========== Results ==========
 
-    fn exec(&self, query: Box<dyn Query>, limit: usize, offset: usize) -> Result<SearchResults, String> {
-        let searcher = self.reader.searcher();
+/// One hit: returned fields plus `_`-prefixed metadata such as `_score`.
+pub type Hit = BTreeMap<String, HitValue>;
 
-        // Use TopDocs with offset for proper pagination + Count for total matching docs
-        let collector = TopDocs::with_limit(limit).and_offset(offset);
-        let (total_count, top) = searcher.search(&query, &(Count, collector)).map_err(|e| e.to_string())?;
+/// A value in a hit. Untagged, so hits encode as plain maps in every encoding.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum HitValue {
+    Null,
+    I64(i64),
+    F64(f64),
+    /// `_score`, kept at tantivy's f32 so it encodes as scored rather than
+    /// widened to f64 digits. Decodes as `F64`.
+    F32(f32),
+    /// Text, or base64 for bytes fields.
+    Str(String),
+    /// `_inner_hits` of a collapsed hit.
+    Hits(Vec<Hit>),
+    /// `_highlight` snippets by field.
+    Map(Hit),
+    /// `_explanation` trees.
+    Json(serde_json::Value),
+}
 
-        let mut results = Vec::with_capacity(top.len());
-        for (score, addr) in &top {
-            let doc: TantivyDocument = searcher.doc(*addr).map_err(|e| e.to_string())?;
-            let mut obj = serde_json::Map::new();
-            for (name, (field, fd)) in &self.field_map {
-                match fd.field_type.as_str() {
-                    "text" => {
-                        if let Some(v) = doc.get_first(*field) {
-                            if let Some(s) = v.as_str() { obj.insert(name.clone(), serde_json::Value::String(s.to_string())); }
-                        }
-                    }
-                    "i64" => {
-                        if let Some(v) = doc.get_first(*field) {
-                            if let Some(n) = v.as_i64() { obj.insert(name.clone(), serde_json::json!(n)); }
-                        }
-                    }
-                    "f64" => {
-                        if let Some(v) = doc.get_first(*field) {
-                            if let Some(n) = v.as_f64() { obj.insert(name.clone(), serde_json::json!(n)); }
-                        }
-                    }
-                    _ => {}
-                }
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SearchResults {
+    pub results: Vec<Hit>,
+    pub count: usize,
+    pub total_count: usize,
+    pub limit: usize,
+    pub offset: usize,
+    /// Distinct groups among all hits, for collapsed searches.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_groups: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aggregations: Option<AggregationResults>,
+    /// Problems recovered from while parsing lenient text queries.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+/// Result of `validate_query`: the request as parsed, with defaults filled in.
+#[derive(Serialize, Debug)]
+pub struct Validation {
+    pub request: serde_json::Value,
+    /// Schema fields the query refers to.
+    pub fields: Vec<String>,
+    /// Debug rendering of the tantivy query tree.
+    pub debug: String,
+    pub warnings: Vec<String>,
+}
+
+/// Column-oriented view of `SearchResults`: one array per field, aligned by
+/// hit position, with `null` where a hit lacks the field.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ColumnarResults {
+    pub columns: BTreeMap<String, Vec<HitValue>>,
+    pub count: usize,
+    pub total_count: usize,
+    pub limit: usize,
+    pub offset: usize,
+    /// Distinct groups among all hits, for collapsed searches.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_groups: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aggregations: Option<AggregationResults>,
+    /// Problems recovered from while parsing lenient text queries.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+impl From<&SearchResults> for ColumnarResults {
+    fn from(r: &SearchResults) -> Self {
+        let mut columns: BTreeMap<String, Vec<HitValue>> = BTreeMap::new();
+        for (i, hit) in r.results.iter().enumerate() {
+            for (k, v) in hit {
+                let col = columns.entry(k.clone()).or_insert_with(|| vec![HitValue::Null; i]);
+                col.resize(i, HitValue::Null);
+                col.push(v.clone());
             }
-            obj.insert("_score".to_string(), serde_json::json!(score));
-            results.push(serde_json::Value::Object(obj));
         }
-        let count = results.len();
-        Ok(SearchResults { results, count, total_count, limit, offset })
+        for col in columns.values_mut() { col.resize(r.results.len(), HitValue::Null); }
+        ColumnarResults {
+            columns, count: r.count, total_count: r.total_count, limit: r.limit, offset: r.offset,
+            total_groups: r.total_groups, aggregations: r.aggregations.clone(), warnings: r.warnings.clone(),
+        }
     }
+}
 
-    fn build_query(&self, qd: &QueryDef) -> Result<Box<dyn Query>, String> {
-        match qd {
-            QueryDef::Text { query, fields, .. } => {
-                let f = self.resolve_fields(fields);
-                let qp = QueryParser::for_index(&self.index, f);
-                qp.parse_query(query).map_err(|e| e.to_string())
+/// Wire encoding for search results. The numeric codes are part of the FFI contract.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultEncoding {
+    Json = 0,
+    MsgPack = 1,
+    MsgPackColumnar = 2,
+}
+
+impl ResultEncoding {
+    pub fn from_code(code: u32) -> Result<Self, Error> {
+        match code {
+            0 => Ok(ResultEncoding::Json),
+            1 => Ok(ResultEncoding::MsgPack),
+            2 => Ok(ResultEncoding::MsgPackColumnar),
+            c => Err(Error::bad_request(format!("unknown result encoding: {}", c))),
+        }
+    }
+
+    pub fn encode(self, r: &SearchResults) -> Result<Vec<u8>, Error> {
+        match self {
+            // serde_json errors convert to bad_request, which a result can't be.
+            ResultEncoding::Json => serde_json::to_vec(r).map_err(|e| Error::internal(format!("serialize: {}", e))),
+            ResultEncoding::MsgPack => Ok(rmp_serde::to_vec_named(r)?),
+            ResultEncoding::MsgPackColumnar => Ok(rmp_serde::to_vec_named(&ColumnarResults::from(r))?),
+        }
+    }
+}
+
+// ========== Auto-Commit ==========
+
+/// Background commit thresholds. A zero value disables that trigger;
+/// with both zero, auto-commit is off.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AutoCommitConfig {
+    #[serde(default)]
+    pub max_docs: u64,
+    #[serde(default)]
+    pub interval_ms: u64,
+}
+
+/// Writer-side state, shared with the auto-commit thread.
+struct WriteState {
+    writer: Mutex<IndexWriter>,
+    reader: IndexReader,
+    // False under the "manual" reload policy: commits only become visible on refresh.
+    reload_on_commit: bool,
+    // Watermark of the last commit made through this writer.
+    committed_opstamp: AtomicU64,
+    // Operations with an opstamp below this watermark are visible to searches.
+    visible_opstamp: Mutex<Opstamp>,
+    visible_cv: Condvar,
+    // Set when the handle is freed; waiters on `visible_cv` then give up.
+    closed: AtomicBool,
+    // Add/delete operations since the last commit.
+    pending: AtomicU64,
+    // Auto-commit's `max_docs`, 0 when that trigger is off; read on every write
+    // so writes only touch the auto-commit lock once the threshold is reached.
+    auto_commit_max_docs: AtomicU64,
+    // Most recent auto-commit failure; cleared by the next successful auto-commit.
+    last_error: Mutex<Option<Error>>,
+}
+
+impl WriteState {
+    fn commit(&self) -> Result<Opstamp, Error> {
+        let mut w = self.writer.lock()?;
+        let opstamp = w.commit()?;
+        self.pending.store(0, Ordering::SeqCst);
+        self.committed_opstamp.fetch_max(opstamp + 1, Ordering::SeqCst);
+        if self.reload_on_commit {
+            self.reader.reload()?;
+            self.mark_visible(opstamp + 1)?;
+        }
+        Ok(opstamp)
+    }
+
+    fn refresh(&self) -> Result<(), Error> {
+        let committed = self.committed_opstamp.load(Ordering::SeqCst);
+        self.reader.reload()?;
+        self.mark_visible(committed)
+    }
+
+    fn mark_visible(&self, watermark: Opstamp) -> Result<(), Error> {
+        let mut v = self.visible_opstamp.lock()?;
+        if watermark > *v {
+            *v = watermark;
+            self.visible_cv.notify_all();
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct AutoCommitSignal {
+    stop: bool,
+    kick: bool,
+}
+
+struct AutoCommitter {
+    state: Arc<WriteState>,
+    signal: Arc<(Mutex<AutoCommitSignal>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl AutoCommitter {
+    /// Start a committer for `config`, or none if every trigger is disabled.
+    fn configure(state: Arc<WriteState>, config: AutoCommitConfig) -> Result<Option<Self>, Error> {
+        if config.max_docs == 0 && config.interval_ms == 0 { return Ok(None); }
+        Self::start(state, config).map(Some)
+    }
+
+    fn start(state: Arc<WriteState>, config: AutoCommitConfig) -> Result<Self, Error> {
+        let signal = Arc::new((Mutex::new(AutoCommitSignal::default()), Condvar::new()));
+        let sig = signal.clone();
+        let interval = config.interval_ms;
+        let thread = std::thread::Builder::new()
+            .name("tantivy-autocommit".to_string())
+            .spawn({
+                let state = state.clone();
+                move || Self::run(state, sig, interval)
+            })
+            .ctx("auto-commit thread")?;
+        state.auto_commit_max_docs.store(config.max_docs, Ordering::SeqCst);
+        Ok(AutoCommitter { state, signal, thread: Some(thread) })
+    }
+
+    fn run(state: Arc<WriteState>, signal: Arc<(Mutex<AutoCommitSignal>, Condvar)>, interval_ms: u64) {
+        let (lock, cv) = &*signal;
+        loop {
+            let stop = {
+                let Ok(mut g) = lock.lock() else { return };
+                g = if interval_ms > 0 {
+                    match cv.wait_timeout_while(g, Duration::from_millis(interval_ms), |s| !s.stop && !s.kick) {
+                        Ok((g, _)) => g,
+                        Err(_) => return,
+                    }
+                } else {
+                    match cv.wait_while(g, |s| !s.stop && !s.kick) {
+                        Ok(g) => g,
+                        Err(_) => return,
+                    }
+                };
+                g.kick = false;
+                g.stop
+            };
+            // Flush whatever is pending, including on shutdown.
+            if state.pending.load(Ordering::SeqCst) > 0 {
+                let r = state.commit();
+                if let Ok(mut slot) = state.last_error.lock() {
+                    *slot = r.err();
+                }
             }
-            QueryDef::Fuzzy { term, distance, fields, .. } => {
-                let f = self.resolve_fields(fields);
-                let words: Vec<String> = term.split_whitespace()
-                    .map(|w| w.to_lowercase().chars().filter(|c| c.is_alphanumeric()).collect::<String>())
-                    .filter(|w| w.len() > 1)
-                    .collect();
-                if words.is_empty() {
-                    return Ok(Box::new(BooleanQuery::new(vec![])));
+            if stop { return; }
+        }
+    }
+
+    fn kick(&self) {
+        if let Ok(mut g) = self.signal.0.lock() {
+            g.kick = true;
+            self.signal.1.notify_one();
+        }
+    }
+
+    fn stop(&mut self) {
+        self.state.auto_commit_max_docs.store(0, Ordering::SeqCst);
+        if let Ok(mut g) = self.signal.0.lock() {
+            g.stop = true;
+            self.signal.1.notify_one();
+        }
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
+
+impl Drop for AutoCommitter {
+    fn drop(&mut self) { self.stop(); }
+}
+
+// ========== Snapshot ==========
+
+/// A pinned searcher generation. Searches through a snapshot see the same
+/// segments (and totals) regardless of commits made after it was acquired.
+pub struct Snapshot {
+    // Id of the index it was acquired from.
+    index_id: u64,
+    searcher: Searcher,
+}
+
+impl Snapshot {
+    pub fn num_docs(&self) -> u64 { self.searcher.num_docs() }
+}
+
+// ========== Index ==========
+
+type FieldMap = HashMap<String, (Field, FieldDef)>;
+
+/// Source of `TantivyIndex::id`.
+static NEXT_INDEX_ID: AtomicU64 = AtomicU64::new(1);
+
+pub struct TantivyIndex {
+    // Unique per open index, so snapshots can be matched to their index.
+    id: u64,
+    index: Index,
+    reader: IndexReader,
+    state: Arc<WriteState>,
+    auto_commit: Mutex<Option<AutoCommitter>>,
+    #[allow(dead_code)]
+    schema: Schema,
+    field_map: FieldMap,
+    search_fields: Vec<(Field, Score)>,
+}
+
+impl TantivyIndex {
+    pub fn create(path: &str, schema_json: &str) -> Result<Self, Error> {
+        Self::create_with_options(path, schema_json, "")
+    }
+
+    pub fn open(path: &str) -> Result<Self, Error> {
+        Self::open_with_options(path, "")
+    }
+
+    /// Create with an `IndexOptions` JSON object (empty string for defaults).
+    pub fn create_with_options(path: &str, schema_json: &str, options_json: &str) -> Result<Self, Error> {
+        let opts = IndexOptions::parse(options_json)?;
+        let _ = std::fs::remove_dir_all(path);
+        std::fs::create_dir_all(path).ctx("mkdir")?;
+
+        let schema_def: SchemaDef =
+            serde_json::from_str(schema_json).ctx("schema")?;
+        let (schema, fmap) = Self::build_schema(&schema_def)?;
+        let sf = Self::resolve_search_fields(&schema_def, &fmap)?;
+
+        std::fs::write(Path::new(path).join("_schema.json"), schema_json)
+            .ctx("save schema")?;
+
+        let index =
+            Index::create_in_dir(Path::new(path), schema.clone())?;
+        Self::finish(index, schema, fmap, sf, &opts)
+    }
+
+    /// Open with an `IndexOptions` JSON object (empty string for defaults).
+    pub fn open_with_options(path: &str, options_json: &str) -> Result<Self, Error> {
+        let opts = IndexOptions::parse(options_json)?;
+        let sj = std::fs::read_to_string(Path::new(path).join("_schema.json"))
+            .ctx("read schema")?;
+        let schema_def: SchemaDef =
+            serde_json::from_str(&sj).ctx("schema")?;
+        let (schema, fmap) = Self::build_schema(&schema_def)?;
+        let index = Index::open_in_dir(Path::new(path))?;
+        let sf = Self::resolve_search_fields(&schema_def, &fmap)?;
+        Self::finish(index, schema, fmap, sf, &opts)
+    }
+
+    fn finish(index: Index, schema: Schema, fmap: FieldMap, sf: Vec<(Field, Score)>, opts: &IndexOptions) -> Result<Self, Error> {
+        let policy = match opts.reload_policy.as_str() {
+            "on_commit" => ReloadPolicy::OnCommitWithDelay,
+            "manual" => ReloadPolicy::Manual,
+            p => return Err(Error::bad_request(format!("unknown reload_policy: {}", p)).with_path("reload_policy")),
+        };
+        let reader = index.reader_builder()
+            .reload_policy(policy)
+            .try_into().ctx("reader")?;
+        let writer = index.writer(256_000_000).ctx("writer")?;
+        let opstamp = index.load_metas().ctx("metas")?.opstamp;
+        let state = Arc::new(WriteState {
+            writer: Mutex::new(writer), reader: reader.clone(),
+            reload_on_commit: opts.reload_policy != "manual", committed_opstamp: AtomicU64::new(opstamp),
+            visible_opstamp: Mutex::new(opstamp), visible_cv: Condvar::new(), closed: AtomicBool::new(false),
+            pending: AtomicU64::new(0), auto_commit_max_docs: AtomicU64::new(0), last_error: Mutex::new(None),
+        });
+        let ac = match &opts.auto_commit {
+            Some(c) => AutoCommitter::configure(state.clone(), c.clone())?,
+            None => None,
+        };
+        Ok(TantivyIndex {
+            id: NEXT_INDEX_ID.fetch_add(1, Ordering::Relaxed),
+            index, reader, state, auto_commit: Mutex::new(ac),
+            schema, field_map: fmap, search_fields: sf,
+        })
+    }
+
+    fn build_schema(def: &SchemaDef) -> Result<(Schema, FieldMap), Error> {
+        let mut sb = Schema::builder();
+        let mut fm = HashMap::new();
+        for fd in &def.fields {
+            if let Some(b) = &fd.bm25 { b.check(&format!("fields.{}.bm25", fd.name))?; }
+            let field = match fd.field_type.as_str() {
+                "text" => {
+                    let mut o = TextOptions::default();
+                    if fd.stored { o = o.set_stored(); }
+                    if fd.indexed {
+                        let tok = match fd.tokenizer.as_str() { "raw" => "raw", "en_stem" => "en_stem", _ => "default" };
+                        let rec = if fd.tokenizer == "raw" { IndexRecordOption::Basic } else { IndexRecordOption::WithFreqsAndPositions };
+                        o = o.set_indexing_options(TextFieldIndexing::default().set_tokenizer(tok).set_index_option(rec));
+                    }
+                    if fd.fast { o = o.set_fast(None); }
+                    sb.add_text_field(&fd.name, o)
                 }
-                let mut word_clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
-                for word in &words {
-                    // Adaptive distance: short words use dist 1 to avoid over-matching
-                    let eff_dist = if word.len() <= 5 { 1u8.min(*distance) } else { *distance };
-                    let per_field: Vec<(Occur, Box<dyn Query>)> = f.iter().map(|fld| {
-                        let t = tantivy::Term::from_field_text(*fld, word);
-                        (Occur::Should, Box::new(FuzzyTermQuery::new(t, eff_dist, true)) as Box<dyn Query>)
-                    }).collect();
-                    word_clauses.push((Occur::Must, Box::new(BooleanQuery::new(per_field)) as Box<dyn Query>));
+                "i64" => {
+                    let mut o = NumericOptions::default();
+                    if fd.stored { o = o.set_stored(); }
+                    if fd.indexed { o = o.set_indexed(); }
+                    if fd.fast { o = o.set_fast(); }
+                    sb.add_i64_field(&fd.name, o)
                 }
-                Ok(Box::new(BooleanQuery::new(word_clauses)))
-            }
-            QueryDef::Phrase { phrase, fields, .. } => {
-                let f = self.resolve_fields(fields);
-                let words: Vec<&str> = phrase.split_whitespace().collect();
-                if words.len() < 2 {
-                    let qp = QueryParser::for_index(&self.index, f);
-                    return qp.parse_query(phrase).map_err(|e| e.to_string());
-                }
-                let clauses: Vec<(Occur, Box<dyn Query>)> = f.iter().map(|fld| {
-                    let terms: Vec<tantivy::Term> = words.iter()
-                        .map(|w| tantivy::Term::from_field_text(*fld, &w.to_lowercase()))
-                        .collect();
-                    (Occur::Should, Box::new(PhraseQuery::new(terms)) as Box<dyn Query>)
-                }).collect();
-                Ok(Box::new(BooleanQuery::new(clauses)))
-            }
-            QueryDef::Prefix { prefix, fields, .. } => {
-                let f = self.resolve_fields(fields);
-                let pat = format!("{}.*", regex_escape(&prefix.to_lowercase()));
-                let clauses: Result<Vec<(Occur, Box<dyn Query>)>, String> = f.iter().map(|fld| {
-                    let rq = RegexQuery::from_pattern(&pat, *fld).map_err(|e| e.to_string())?;
-                    Ok((Occur::Should, Box::new(rq) as Box<dyn Query>))
-                }).collect();
-                Ok(Box::new(BooleanQuery::new(clauses?)))
-            }
-            QueryDef::TermMatch { field, value, .. } => {
-                let (fld, fd) = self.field_map.get(field)
-                    .ok_or_else(|| format!("unknown field: {}", field))?;
+                "f64" => {
+                    let mut o = NumericOptions::default();
+                    if fd.stored { o = o.set_stored(); }
+                    if fd.indexed { o = o.set_indexed(); }
+                    if fd.fast { o = o.set_fast(); }
+                    sb.add_f64_field(&fd.name, o)
+                }
+                "bytes" => {
+                    let mut o = BytesOptions::default();
+                    if fd.stored { o = o.set_stored(); }
+                    if fd.indexed { o = o.set_indexed(); }
+                    if fd.fast { o = o.set_fast(); }
+                    sb.add_bytes_field(&fd.name, o)
+                }
+                t => return Err(Error::bad_request(format!("unknown type: {}", t)).with_path(format!("fields.{}.type", fd.name))),
+            };
+            fm.insert(fd.name.clone(), (field, fd.clone()));
+        }
+        Ok((sb.build(), fm))
+    }
+
+    fn resolve_search_fields(def: &SchemaDef, fm: &FieldMap) -> Result<Vec<(Field, Score)>, Error> {
+        if !def.search_fields.is_empty() {
+            weighted_fields(&def.search_fields, fm, "search_fields")
+        } else {
+            Ok(fm.values().filter(|(_, d)| d.field_type == "text" && d.indexed && d.tokenizer != "raw").map(|(f, _)| (*f, 1.0)).collect())
+        }
+    }
+
+    // ===== Document Operations =====
+
+    /// Add a JSON document. Returns the opstamp of the add operation.
+    pub fn add_doc(&self, doc_json: &str) -> Result<Opstamp, Error> {
+        let map: HashMap<String, serde_json::Value> =
+            serde_json::from_str(doc_json).ctx("doc")?;
+        let mut doc = TantivyDocument::new();
+        for (name, val) in &map {
+            if let Some((field, fd)) = self.field_map.get(name) {
                 match fd.field_type.as_str() {
-                    "text" => {
-                        let s = value.as_str().unwrap_or("");
-                        let t = tantivy::Term::from_field_text(*fld, s);
-                        Ok(Box::new(TermQuery::new(t, IndexRecordOption::Basic)))
-                    }
+                    "text" => { if let Some(s) = val.as_str() { doc.add_text(*field, s); } }
                     "i64" => {
-                        let n = value.as_i64().unwrap_or(0);
-                        let t = tantivy::Term::from_field_i64(*fld, n);
-                        Ok(Box::new(TermQuery::new(t, IndexRecordOption::Basic)))
-                    }
-                    "f64" => {
-                        let n = value.as_f64().unwrap_or(0.0);
-                        let t = tantivy::Term::from_field_f64(*fld, n);
-                        Ok(Box::new(TermQuery::new(t, IndexRecordOption::Basic)))
+                        if let Some(n) = val.as_i64() { doc.add_i64(*field, n); }
+                        else if let Some(n) = val.as_f64() { doc.add_i64(*field, n as i64); }
                     }
-                    _ => Err("unsupported term type".to_string()),
+                    "f64" => { if let Some(n) = val.as_f64() { doc.add_f64(*field, n); } }
+                    "bytes" => { if let Some(s) = val.as_str() { doc.add_bytes(*field, decode_b64(s, name)?); } }
+                    _ => {}
                 }
             }
-            QueryDef::RangeI64 { field, min, max, .. } => {
-                let lo = min.unwrap_or(i64::MIN);
-                let hi = max.map(|v| v + 1).unwrap_or(i64::MAX);
-                let q = tantivy::query::RangeQuery::new_i64(field.clone(), lo..hi);
-                Ok(Box::new(q))
-            }
-            QueryDef::RangeF64 { field, min, max, .. } => {
-                let lo_bound = match min {
-                    Some(v) => std::ops::Bound::Included(*v),
-                    None => std::ops::Bound::Unbounded,
-                };
-                let hi_bound = match max {
-                    Some(v) => std::ops::Bound::Included(*v),
-                    None => std::ops::Bound::Unbounded,
-                };
-                let q = tantivy::query::RangeQuery::new_f64_bounds(field.clone(), lo_bound, hi_bound);
-                Ok(Box::new(q))
-            }
-            QueryDef::Bool { must, should, must_not, .. } => {
-                let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
-                for sub in must { clauses.push((Occur::Must, self.build_query(sub)?)); }
-                for sub in should { clauses.push((Occur::Should, self.build_query(sub)?)); }
-                for sub in must_not { clauses.push((Occur::MustNot, self.build_query(sub)?)); }
-                Ok(Box::new(BooleanQuery::new(clauses)))
-            }
-            QueryDef::All { .. } => {
-                Ok(Box::new(tantivy::query::AllQuery))
-            }
         }
+        let opstamp = {
+            let w = self.state.writer.lock()?;
+            let op = w.add_document(doc)?;
+            self.state.pending.fetch_add(1, Ordering::SeqCst);
+            op
+        };
+        self.maybe_kick_auto_commit();
+        Ok(opstamp)
     }
 
-    fn resolve_fields(&self, names: &[String]) -> Vec<Field> {
-        if names.is_empty() {
-            self.search_fields.clone()
-        } else {
-            names.iter().filter_map(|n| self.field_map.get(n).map(|(f, _)| *f)).collect()
+    /// Delete all documents whose `field` holds exactly `value`. Returns the opstamp.
+    pub fn delete_term(&self, field: &str, value: &serde_json::Value) -> Result<Opstamp, Error> {
+        let t = self.term_for(field, value)?;
+        let opstamp = {
+            let w = self.state.writer.lock()?;
+            let op = w.delete_term(t);
+            self.state.pending.fetch_add(1, Ordering::SeqCst);
+            op
+        };
+        self.maybe_kick_auto_commit();
+        Ok(opstamp)
+    }
+
+    /// Commit pending writes. Returns the commit opstamp. Under the "on_commit"
+    /// reload policy the reader is reloaded too; under "manual" call `refresh`.
+    pub fn commit(&self) -> Result<Opstamp, Error> {
+        self.state.commit()
+    }
+
+    /// Reload the reader so searches see every commit made so far.
+    pub fn refresh(&self) -> Result<(), Error> {
+        self.state.refresh()
+    }
+
+    /// Enable, reconfigure or (with an all-zero config) disable background commits.
+    pub fn set_auto_commit(&self, config_json: &str) -> Result<(), Error> {
+        let config: AutoCommitConfig =
+            serde_json::from_str(config_json).ctx("auto_commit")?;
+        let mut ac = self.auto_commit.lock()?;
+        // Dropping the previous committer flushes and joins its thread.
+        *ac = None;
+        *ac = AutoCommitter::configure(self.state.clone(), config)?;
+        Ok(())
+    }
+
+    /// Error from the most recent failed background commit, if any.
+    pub fn last_error(&self) -> Option<Error> {
+        self.state.last_error.lock().ok().and_then(|e| e.clone())
+    }
+
+    fn maybe_kick_auto_commit(&self) {
+        let max_docs = self.state.auto_commit_max_docs.load(Ordering::SeqCst);
+        if max_docs == 0 || self.state.pending.load(Ordering::SeqCst) < max_docs { return; }
+        if let Ok(ac) = self.auto_commit.lock() {
+            if let Some(ac) = ac.as_ref() { ac.kick(); }
         }
     }
-}
 
-fn regex_escape(s: &str) -> String {
-    let mut o = String::with_capacity(s.len() * 2);
-    for c in s.chars() {
-        if "\\.*+?()[]{}^$|".contains(c) { o.push('\\'); }
-        o.push(c);
+    /// Block until the reader reflects every operation up to `opstamp`, or until
+    /// `timeout_ms` elapses. Returns true if the opstamp became visible.
+    /// Fails with "index closed" once `close` was called.
+    pub fn wait_for_opstamp(&self, opstamp: Opstamp, timeout_ms: u64) -> Result<bool, Error> {
+        let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+        let mut v = self.state.visible_opstamp.lock()?;
+        while *v <= opstamp {
+            if self.state.closed.load(Ordering::SeqCst) { return Err(Error::not_found("index closed")); }
+            let now = Instant::now();
+            if now >= deadline { return Ok(false); }
+            v = self.state.visible_cv.wait_timeout(v, deadline - now)?.0;
+        }
+        Ok(true)
+    }
+
+    /// Wake every `wait_for_opstamp` caller with an "index closed" error; the
+    /// opstamps they wait for can no longer arrive once the handle is freed.
+    pub fn close(&self) -> Result<(), Error> {
+        let _v = self.state.visible_opstamp.lock()?;
+        self.state.closed.store(true, Ordering::SeqCst);
+        self.state.visible_cv.notify_all();
+        Ok(())
+    }
+
+    pub fn num_docs(&self) -> u64 { self.reader.searcher().num_docs() }
+
+    // ===== Search =====
+
+    pub fn search(&self, query_json: &str) -> Result<SearchResults, Error> {
+        self.search_with(&self.reader.searcher(), query_json)
+    }
+
+    /// Pin the current searcher generation for consistent multi-page reads.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot { index_id: self.id, searcher: self.reader.searcher() }
+    }
+
+    /// Search against a snapshot acquired from this index.
+    pub fn search_snapshot(&self, snap: &Snapshot, query_json: &str) -> Result<SearchResults, Error> {
+        if snap.index_id != self.id {
+            return Err(Error::bad_request("snapshot belongs to a different index"));
+        }
+        self.search_with(&snap.searcher, query_json)
+    }
+
+    fn search_with(&self, searcher: &Searcher, query_json: &str) -> Result<SearchResults, Error> {
+        let p = self.prepare(searcher, query_json)?;
+        let mut results = self.exec(searcher, p.query, &p.req)?;
+        results.warnings = p.warnings;
+        Ok(results)
+    }
+
+    /// Parse a request and build its tantivy query.
+    fn prepare(&self, searcher: &Searcher, query_json: &str) -> Result<Prepared, Error> {
+        let mut req = SearchRequest::parse(query_json)?;
+        if req.scoring != "bm25" && req.scoring != "none" {
+            return Err(Error::bad_request(format!("unknown scoring: {}", req.scoring)).with_path("scoring"));
+        }
+        match req.min_score {
+            Some(m) if !m.is_finite() => return Err(Error::bad_request(format!("invalid min_score: {}", m)).with_path("min_score")),
+            Some(_) if req.scoring == "none" => return Err(Error::bad_request("min_score needs scoring").with_path("min_score")),
+            _ => {}
+        }
+        if req.count_only {
+            let skipped = [("aggs", req.aggs.is_some()), ("collapse", req.collapse.is_some()), ("highlight", req.highlight.is_some()), ("explain", req.explain)];
+            if let Some((name, _)) = skipped.iter().find(|(_, set)| *set) {
+                return Err(Error::bad_request(format!("count_only can't be combined with {}", name)).with_path("count_only"));
+            }
+        }
+        if req.sort.is_some() && matches!(req.query.def, QueryDef::FunctionScore { .. }) {
+            return Err(Error::bad_request("function_score can't be combined with sort").with_path("sort"));
+        }
+        let mut cx = BuildContext { searcher: searcher.clone(), bm25: self.similarity(&req)?, warnings: std::mem::take(&mut req.warnings) };
+        let query = self.build_root(&req.query, &mut cx).map_err(|e| e.under(req.query_path()))?;
+        Ok(Prepared { req, query, warnings: cx.warnings })
+    }
+
+    /// BM25 `(k1, b)` per indexed text field, for the fields where the request
+    /// or schema departs from tantivy's defaults.
+    fn similarity(&self, req: &SearchRequest) -> Result<HashMap<Field, (Score, Score)>, Error> {
+        let none = Bm25Request::default();
+        let r = req.bm25.as_ref().unwrap_or(&none);
+        r.all.check("bm25")?;
+        for (name, d) in &r.fields {
+            let path = format!("bm25.fields.{}", name);
+            if !self.field_map.contains_key(name) { return Err(Error::schema_mismatch(format!("unknown field: {}", name)).with_path(path)); }
+            d.check(&path)?;
+        }
+        let mut out = HashMap::new();
+        for (name, (f, fd)) in &self.field_map {
+            if fd.field_type != "text" || !fd.indexed { continue; }
+            let levels = [r.fields.get(name), Some(&r.all), fd.bm25.as_ref()];
+            let k1 = levels.iter().flatten().find_map(|d| d.k1).unwrap_or(BM25_K1);
+            let b = levels.iter().flatten().find_map(|d| d.b).unwrap_or(BM25_B);
+            if k1 != BM25_K1 || b != BM25_B { out.insert(*f, (k1, b)); }
+        }
+        Ok(out)
+    }
+
+    /// Parse and build a query without running it. Errors carry the JSON path
+    /// of the offending node, e.g. `must[2].field`.
+    pub fn validate_query(&self, query_json: &str) -> Result<Validation, Error> {
+        let searcher = self.reader.searcher();
+        let Prepared { req, query, warnings } = self.prepare(&searcher, query_json)?;
+        query.weight(EnableScoring::disabled_from_searcher(&searcher)).map_err(|e| Error::from(e).under(req.query_path()))?;
+        let mut fields = BTreeSet::new();
+        self.collect_fields(&req.query.def, &mut fields);
+        query.query_terms(&mut |t, _| { fields.insert(self.schema.get_field_name(t.field()).to_string()); });
+        Ok(Validation {
+            request: serde_json::to_value(&req)?,
+            fields: fields.into_iter().collect(),
+            debug: format!("{:?}", query),
+            warnings,
+        })
+    }
+
+    fn exec(&self, searcher: &Searcher, query: Box<dyn Query>, req: &SearchRequest) -> Result<SearchResults, Error> {
+        // prepare rejects function_score with sort, so functions only run for score order.
+        let functions = FunctionScorer::open(searcher, &req.query.def, &self.field_map)?.map(Arc::new);
+        let run = Collection::new(req, functions.clone());
+        if req.count_only {
+            let total_count = run.collect(searcher, &*query, &Count)?;
+            return Ok(SearchResults {
+                results: Vec::new(), count: 0, total_count, limit: req.limit, offset: req.offset,
+                total_groups: None, aggregations: None, warnings: Vec::new(),
+            });
+        }
+        let explainer = match (req.explain, run.scoring) {
+            (false, _) => None,
+            (true, true) => Some(query.weight(EnableScoring::enabled_from_searcher(searcher))?),
+            (true, false) => Some(query.weight(EnableScoring::disabled_from_searcher(searcher))?),
+        };
+        let mut hits = HitRenderer {
+            index: self, searcher,
+            stored: self.stored_projection(req)?,
+            docvalues: self.docvalue_projection(req)?,
+            snippets: self.snippet_generators(searcher, &*query, req)?,
+            explainer, functions: functions.clone(), columns: HashMap::new(),
+        };
+        let aggs = match &req.aggs {
+            Some(a) => {
+                let a: Aggregations = serde_json::from_value(a.clone()).ctx("aggs")?;
+                Some(AggregationCollector::from_aggs(a, Default::default()))
+            }
+            None => None,
+        };
+        if let Some(c) = &req.collapse {
+            return self.exec_collapsed(searcher, &*query, req, c, aggs, hits);
+        }
+        // Use TopDocs with offset for proper pagination + Count for total matching docs.
+        // limit 0 (e.g. aggregations only) skips hit collection entirely.
+        let collector = (req.limit > 0).then(|| TopDocs::with_limit(req.limit).and_offset(req.offset));
+        let (key, total_count, top, agg_results) = match &req.sort {
+            None => {
+                let (n, top, a) = match &functions {
+                    Some(fs) => {
+                        let fs = fs.clone();
+                        let collector = collector.map(|c| c.tweak_score(move |reader: &SegmentReader| {
+                            let (fs, cols) = (fs.clone(), fs.segment(reader));
+                            move |doc, score| fs.score(score, &cols, doc)
+                        }));
+                        run.collect(searcher, &*query, &(Count, collector, aggs))?
+                    }
+                    None => run.collect(searcher, &*query, &(Count, collector, aggs))?,
+                };
+                ("_score", n, top.unwrap_or_default().into_iter().map(|(s, d)| (HitValue::F32(s), d)).collect::<Vec<_>>(), a)
+            }
+            Some(sort) => {
+                let (ty, order) = self.sort_spec(sort)?;
+                if ty == "i64" {
+                    let (n, top, a) = run.collect(searcher, &*query, &(Count, collector.map(|c| c.order_by_fast_field::<i64>(&sort.field, order)), aggs))?;
+                    ("_sort", n, top.unwrap_or_default().into_iter().map(|(v, d)| (HitValue::I64(v), d)).collect(), a)
+                } else {
+                    let (n, top, a) = run.collect(searcher, &*query, &(Count, collector.map(|c| c.order_by_fast_field::<f64>(&sort.field, order)), aggs))?;
+                    ("_sort", n, top.unwrap_or_default().into_iter().map(|(v, d)| (HitValue::F64(v), d)).collect(), a)
+                }
+            }
+        };
+
+        let mut results = Vec::with_capacity(top.len());
+        for (value, addr) in top {
+            let mut obj = hits.render(addr)?;
+            obj.insert(key.to_string(), value);
+            results.push(obj);
+        }
+        let count = results.len();
+        Ok(SearchResults {
+            results, count, total_count, limit: req.limit, offset: req.offset,
+            total_groups: None, aggregations: agg_results, warnings: Vec::new(),
+        })
+    }
+
+    /// `exec` for a request with `collapse`: page over groups, each shown as
+    /// its best hit, and count groups as well as hits.
+    fn exec_collapsed(
+        &self, searcher: &Searcher, query: &dyn Query, req: &SearchRequest, c: &CollapseDef,
+        aggs: Option<AggregationCollector>, mut hits: HitRenderer,
+    ) -> Result<SearchResults, Error> {
+        let field = match self.field_map.get(&c.field) {
+            Some((_, fd)) if fd.fast => (c.field.clone(), fd.clone()),
+            Some(_) => return Err(Error::schema_mismatch(format!("field is not fast: {}", c.field)).with_path("collapse.field")),
+            None => return Err(Error::schema_mismatch(format!("unknown field: {}", c.field)).with_path("collapse.field")),
+        };
+        let sort = match &req.sort {
+            Some(sort) => {
+                let (_, order) = self.sort_spec(sort)?;
+                let (_, fd) = &self.field_map[&sort.field];
+                Some((sort.field.clone(), fd.clone(), matches!(order, Order::Desc)))
+            }
+            None => None,
+        };
+        let desc = sort.as_ref().is_none_or(|s| s.2);
+        let spec = GroupSpec { field, sort, functions: hits.functions.clone() };
+        let collector = CollapseCollector { spec: spec.clone(), top: req.offset.saturating_add(req.limit) };
+        let run = Collection::new(req, hits.functions.clone());
+        let (total_count, collapsed, agg_results) = run.collect(searcher, query, &(Count, collector, aggs))?;
+        let total_groups = collapsed.total_groups;
+        let page: Vec<_> = collapsed.groups.into_iter().skip(req.offset).collect();
+
+        // The first pass only kept each group's best hit; fetch the rest of
+        // the inner hits for the groups on this page.
+        let inner = if c.inner_hits > 1 && !page.is_empty() {
+            let groups = Arc::new(page.iter().map(|(group, _, _)| group.clone()).collect());
+            run.collect(searcher, query, &InnerHitsCollector { spec, size: c.inner_hits, groups })?
+        } else {
+            InnerHits::new()
+        };
+        let key = if req.sort.is_some() { "_sort" } else { "_score" };
+        let value = |rank: Rank| if req.sort.is_some() { rank.value(desc) } else { rank.score() };
+        let mut results = Vec::new();
+        for (group, rank, addr) in page {
+            let mut obj = hits.render(addr)?;
+            obj.insert(key.to_string(), value(rank));
+            obj.insert("_group".to_string(), group.value());
+            if c.inner_hits > 0 {
+                let top = inner.get(&group).cloned().unwrap_or_else(|| vec![(rank, addr)]);
+                let inner = top.iter().map(|&(rank, addr)| {
+                    let mut h = hits.render(addr)?;
+                    h.insert(key.to_string(), value(rank));
+                    Ok(h)
+                }).collect::<Result<Vec<_>, Error>>()?;
+                obj.insert("_inner_hits".to_string(), HitValue::Hits(inner));
+            }
+            results.push(obj);
+        }
+        let count = results.len();
+        Ok(SearchResults {
+            results, count, total_count, limit: req.limit, offset: req.offset,
+            total_groups: Some(total_groups), aggregations: agg_results, warnings: Vec::new(),
+        })
+    }
+
+    /// Stored fields to load from the doc store for each hit. Names in
+    /// `fields` and `exclude_fields` must be stored fields of the schema.
+    fn stored_projection(&self, req: &SearchRequest) -> Result<Vec<(&String, &Field, &FieldDef)>, Error> {
+        let fields_key = if req.flat { "return_fields" } else { "fields" };
+        for (key, names) in [(fields_key, &req.fields), ("exclude_fields", &req.exclude_fields)] {
+            for (i, name) in names.iter().enumerate() {
+                let path = format!("{}[{}]", key, i);
+                match self.field_map.get(name) {
+                    Some((_, fd)) if fd.stored => {}
+                    Some(_) => return Err(Error::schema_mismatch(format!("field is not stored: {}", name)).with_path(path)),
+                    None => return Err(Error::schema_mismatch(format!("unknown field: {}", name)).with_path(path)),
+                }
+            }
+        }
+        let all = req.fields.is_empty();
+        if all && !req.docvalue_fields.is_empty() { return Ok(Vec::new()); }
+        Ok(self.field_map.iter()
+            .filter(|(name, (_, fd))| fd.stored && (all || req.fields.contains(name)))
+            .filter(|(name, _)| !req.exclude_fields.contains(name))
+            .map(|(name, (f, fd))| (name, f, fd))
+            .collect())
+    }
+
+    fn docvalue_projection<'a>(&'a self, req: &'a SearchRequest) -> Result<Vec<(&'a String, &'a FieldDef)>, Error> {
+        req.docvalue_fields.iter().enumerate().map(|(i, name)| {
+            let path = format!("docvalue_fields[{}]", i);
+            let (_, fd) = self.field_map.get(name)
+                .ok_or_else(|| Error::schema_mismatch(format!("unknown field: {}", name)).with_path(path.clone()))?;
+            if !fd.fast {
+                return Err(Error::schema_mismatch(format!("field is not fast: {}", name)).with_path(path));
+            }
+            Ok((name, fd))
+        }).collect()
+    }
+
+    fn snippet_generators<'a>(&self, searcher: &Searcher, query: &dyn Query, req: &'a SearchRequest) -> Result<Vec<(&'a String, SnippetGenerator)>, Error> {
+        let Some(hl) = &req.highlight else { return Ok(Vec::new()) };
+        hl.fields.iter().enumerate().map(|(i, name)| {
+            let path = format!("highlight.fields[{}]", i);
+            match self.field_map.get(name) {
+                Some((f, fd)) if fd.field_type == "text" && fd.stored => {
+                    let mut gen = SnippetGenerator::create(searcher, query, *f)?;
+                    gen.set_max_num_chars(hl.max_chars);
+                    Ok((name, gen))
+                }
+                Some(_) => Err(Error::schema_mismatch(format!("field is not a stored text field: {}", name)).with_path(path)),
+                None => Err(Error::schema_mismatch(format!("unknown field: {}", name)).with_path(path)),
+            }
+        }).collect()
+    }
+
+    /// Value type and order of a sort key; only i64/f64 fast fields are sortable.
+    fn sort_spec(&self, sort: &SortDef) -> Result<(&str, Order), Error> {
+        let order = match sort.order.as_str() {
+            "asc" => Order::Asc,
+            "desc" => Order::Desc,
+            other => return Err(Error::bad_request(format!("unknown sort order: {}", other)).with_path("sort.order")),
+        };
+        match self.field_map.get(&sort.field) {
+            Some((_, fd)) if fd.fast && (fd.field_type == "i64" || fd.field_type == "f64") => Ok((fd.field_type.as_str(), order)),
+            Some(_) => Err(Error::schema_mismatch(format!("field is not a fast i64/f64 field: {}", sort.field)).with_path("sort.field")),
+            None => Err(Error::schema_mismatch(format!("unknown field: {}", sort.field)).with_path("sort.field")),
+        }
+    }
+
+    fn read_stored(&self, doc: &TantivyDocument, fields: &[(&String, &Field, &FieldDef)], obj: &mut Hit) {
+        for &(name, field, fd) in fields {
+            match fd.field_type.as_str() {
+                "text" => {
+                    if let Some(v) = doc.get_first(*field) {
+                        if let Some(s) = v.as_str() { obj.insert(name.clone(), HitValue::Str(s.to_string())); }
+                    }
+                }
+                "i64" => {
+                    if let Some(v) = doc.get_first(*field) {
+                        if let Some(n) = v.as_i64() { obj.insert(name.clone(), HitValue::I64(n)); }
+                    }
+                }
+                "f64" => {
+                    if let Some(v) = doc.get_first(*field) {
+                        if let Some(n) = v.as_f64() { obj.insert(name.clone(), HitValue::F64(n)); }
+                    }
+                }
+                "bytes" => {
+                    if let Some(v) = doc.get_first(*field) {
+                        if let Some(b) = v.as_bytes() { obj.insert(name.clone(), HitValue::Str(B64.encode(b))); }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Build the root node. A root `function_score` builds only its inner
+    /// query; `exec` applies the functions while collecting top docs.
+    fn build_root(&self, node: &QueryNode, cx: &mut BuildContext) -> Result<Box<dyn Query>, Error> {
+        let QueryDef::FunctionScore { query, functions, score_mode, boost_mode } = &node.def else {
+            return self.build_query(node, cx);
+        };
+        if !["multiply", "sum", "avg", "max", "min"].contains(&score_mode.as_str()) {
+            return Err(Error::bad_request(format!("unknown score_mode: {}", score_mode)).with_path("score_mode"));
+        }
+        if !["multiply", "sum", "replace"].contains(&boost_mode.as_str()) {
+            return Err(Error::bad_request(format!("unknown boost_mode: {}", boost_mode)).with_path("boost_mode"));
+        }
+        for (i, f) in functions.iter().enumerate() {
+            f.check(&self.field_map).map_err(|e| e.under(&format!("functions[{}]", i)))?;
+        }
+        let q = self.build_query(query, cx).map_err(|e| e.under("query"))?;
+        with_boost(q, node.boost)
+    }
+
+    /// Build a tantivy query from a node. Recoverable problems (lenient text
+    /// parsing) are appended to `cx.warnings`.
+    fn build_query(&self, node: &QueryNode, cx: &mut BuildContext) -> Result<Box<dyn Query>, Error> {
+        let q = self.build_def(&node.def, cx)?;
+        with_boost(q, node.boost)
+    }
+
+    fn build_def(&self, qd: &QueryDef, cx: &mut BuildContext) -> Result<Box<dyn Query>, Error> {
+        match qd {
+            QueryDef::Text { query, fields, default_operator, lenient, fuzzy } => {
+                // Terms of fields with custom BM25 get swapped out after parsing,
+                // so their weights are applied then rather than by the parser.
+                let mut weights = HashMap::new();
+                let fields: Vec<(Field, Score)> = self.resolve_fields(fields)?.into_iter().map(|(f, w)| {
+                    let fuzzy_field = fuzzy.keys().any(|n| self.field_map.get(n).is_some_and(|(ff, _)| *ff == f));
+                    if !cx.bm25.contains_key(&f) || fuzzy_field { return (f, w); }
+                    weights.insert(f, w);
+                    (f, 1.0)
+                }).collect();
+                let mut qp = self.parser(&fields);
+                match default_operator.as_str() {
+                    "or" => {}
+                    "and" => qp.set_conjunction_by_default(),
+                    other => return Err(Error::bad_request(format!("unknown default_operator: {}", other)).with_path("default_operator")),
+                }
+                for (name, fz) in fuzzy {
+                    let (f, _) = self.field_map.get(name)
+                        .ok_or_else(|| Error::schema_mismatch(format!("unknown field: {}", name)).with_path(format!("fuzzy.{}", name)))?;
+                    qp.set_field_fuzzy(*f, fz.prefix, fz.distance, fz.transpositions);
+                }
+                let q = if *lenient {
+                    let (q, errs) = qp.parse_query_lenient(query);
+                    cx.warnings.extend(errs.into_iter().map(|e| e.to_string()));
+                    q
+                } else {
+                    qp.parse_query(query)?
+                };
+                Ok(self.rescore(q, cx, &weights))
+            }
+            QueryDef::Fuzzy { term, distance, fields, .. } => {
+                let f = self.resolve_fields(fields)?;
+                self.warn_bm25(cx, "fuzzy", f.iter().map(|(fld, _)| *fld));
+                let words: Vec<String> = term.split_whitespace()
+                    .map(|w| w.to_lowercase().chars().filter(|c| c.is_alphanumeric()).collect::<String>())
+                    .filter(|w| w.len() > 1)
+                    .collect();
+                if words.is_empty() {
+                    return Ok(Box::new(BooleanQuery::new(vec![])));
+                }
+                let mut word_clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+                for word in &words {
+                    // Adaptive distance: short words use dist 1 to avoid over-matching
+                    let eff_dist = if word.len() <= 5 { 1u8.min(*distance) } else { *distance };
+                    let per_field: Vec<(Occur, Box<dyn Query>)> = f.iter().map(|(fld, w)| {
+                        let t = tantivy::Term::from_field_text(*fld, word);
+                        (Occur::Should, boosted(Box::new(FuzzyTermQuery::new(t, eff_dist, true)), *w))
+                    }).collect();
+                    word_clauses.push((Occur::Must, Box::new(BooleanQuery::new(per_field)) as Box<dyn Query>));
+                }
+                Ok(Box::new(BooleanQuery::new(word_clauses)))
+            }
+            QueryDef::Phrase { phrase, fields, slop } => {
+                // Terms as each field's analyzer produced them at index time.
+                let mut clauses = Vec::new();
+                for (fld, w) in self.resolve_fields(fields)? {
+                    let terms = self.analyze(fld, phrase)?;
+                    let q: Box<dyn Query> = match terms.len() {
+                        0 => Box::new(EmptyQuery),
+                        1 => term_query(terms[0].clone(), IndexRecordOption::WithFreqs, &cx.bm25),
+                        _ => {
+                            self.warn_bm25(cx, "phrase", [fld]);
+                            let mut pq = PhraseQuery::new(terms);
+                            pq.set_slop(*slop);
+                            Box::new(pq)
+                        }
+                    };
+                    clauses.push((Occur::Should, boosted(q, w)));
+                }
+                Ok(Box::new(BooleanQuery::new(clauses)))
+            }
+            QueryDef::Proximity { query, distance, ordered, fields } => {
+                let clauses = self.resolve_fields(fields)?.into_iter().map(|(fld, w)| {
+                    let terms = self.analyze(fld, query)?;
+                    let q: Box<dyn Query> = match terms.len() {
+                        0 => Box::new(EmptyQuery),
+                        1 => term_query(terms[0].clone(), IndexRecordOption::WithFreqs, &cx.bm25),
+                        _ => Box::new(ProximityQuery::new(terms, *distance, *ordered, cx.bm25.get(&fld).copied())),
+                    };
+                    Ok((Occur::Should, boosted(q, w)))
+                }).collect::<Result<Vec<_>, Error>>()?;
+                Ok(Box::new(BooleanQuery::new(clauses)))
+            }
+            QueryDef::Prefix { prefix, fields, .. } => {
+                let f = self.resolve_fields(fields)?;
+                self.warn_bm25(cx, "prefix", f.iter().map(|(fld, _)| *fld));
+                let pat = format!("{}.*", regex_escape(&prefix.to_lowercase()));
+                let clauses = f.iter().map(|(fld, w)| {
+                    let rq = RegexQuery::from_pattern(&pat, *fld)?;
+                    Ok((Occur::Should, boosted(Box::new(rq), *w)))
+                }).collect::<Result<Vec<_>, Error>>()?;
+                Ok(Box::new(BooleanQuery::new(clauses)))
+            }
+            QueryDef::Regex { pattern, fields, case_insensitive } => {
+                check_pattern(pattern, pattern)?;
+                self.warn_bm25(cx, "regex", self.resolve_fields(fields)?.into_iter().map(|(fld, _)| fld));
+                self.pattern_query(pattern, fields, *case_insensitive)
+            }
+            QueryDef::Wildcard { pattern, fields, case_insensitive } => {
+                let regex = wildcard_to_regex(pattern);
+                check_pattern(pattern, &regex)?;
+                self.warn_bm25(cx, "wildcard", self.resolve_fields(fields)?.into_iter().map(|(fld, _)| fld));
+                self.pattern_query(&regex, fields, *case_insensitive)
+            }
+            QueryDef::TermMatch { field, value, .. } => {
+                let t = self.term_for(field, value)?;
+                Ok(term_query(t, IndexRecordOption::Basic, &cx.bm25))
+            }
+            QueryDef::Terms { field, values } => {
+                let terms = values.iter().enumerate()
+                    .map(|(i, v)| self.term_for(field, v).map_err(|e| match e.path.as_deref() {
+                        Some("value") => e.with_path(format!("values[{}]", i)),
+                        _ => e,
+                    }))
+                    .collect::<Result<Vec<_>, Error>>()?;
+                self.warn_bm25(cx, "terms", terms.first().map(|t| t.field()));
+                Ok(Box::new(TermSetQuery::new(terms)))
+            }
+            QueryDef::RangeI64 { field, min, max, .. } => {
+                self.check_field_type(field, "i64")?;
+                // Inclusive bounds, so `max: i64::MAX` needs no `+ 1`.
+                let lo_bound = min.map_or(std::ops::Bound::Unbounded, std::ops::Bound::Included);
+                let hi_bound = max.map_or(std::ops::Bound::Unbounded, std::ops::Bound::Included);
+                let q = RangeQuery::new_i64_bounds(field.clone(), lo_bound, hi_bound);
+                Ok(Box::new(q))
+            }
+            QueryDef::RangeF64 { field, min, max, .. } => {
+                self.check_field_type(field, "f64")?;
+                let lo_bound = match min {
+                    Some(v) => std::ops::Bound::Included(*v),
+                    None => std::ops::Bound::Unbounded,
+                };
+                let hi_bound = match max {
+                    Some(v) => std::ops::Bound::Included(*v),
+                    None => std::ops::Bound::Unbounded,
+                };
+                let q = RangeQuery::new_f64_bounds(field.clone(), lo_bound, hi_bound);
+                Ok(Box::new(q))
+            }
+            QueryDef::Bool { must, should, must_not, minimum_should_match } => {
+                let min = match minimum_should_match {
+                    Some(m) => m.resolve(should.len())?,
+                    None => 0,
+                };
+                let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+                for q in self.build_children("must", must, cx)? { clauses.push((Occur::Must, q)); }
+                let shoulds = self.build_children("should", should, cx)?;
+                if min > 0 {
+                    clauses.push((Occur::Must, Box::new(MinShouldMatchQuery::new(shoulds, min))));
+                } else {
+                    clauses.extend(shoulds.into_iter().map(|q| (Occur::Should, q)));
+                }
+                for q in self.build_children("must_not", must_not, cx)? { clauses.push((Occur::MustNot, q)); }
+                // A bool with only must_not clauses excludes from all documents.
+                if must.is_empty() && should.is_empty() && !must_not.is_empty() {
+                    clauses.push((Occur::Must, Box::new(tantivy::query::AllQuery)));
+                }
+                Ok(Box::new(BooleanQuery::new(clauses)))
+            }
+            QueryDef::DisMax { queries, tie_breaker } => {
+                if !(0.0..=1.0).contains(tie_breaker) {
+                    return Err(Error::bad_request(format!("invalid tie_breaker: {}", tie_breaker)).with_path("tie_breaker"));
+                }
+                let subs = self.build_children("queries", queries, cx)?;
+                Ok(Box::new(DisjunctionMaxQuery::with_tie_breaker(subs, *tie_breaker)))
+            }
+            QueryDef::Exists { field } => {
+                let (_, fd) = self.field_map.get(field)
+                    .ok_or_else(|| Error::schema_mismatch(format!("unknown field: {}", field)).with_path("field"))?;
+                if fd.fast { return Ok(Box::new(ExistsQuery::new_exists_query(field.clone()))); }
+                if !fd.indexed {
+                    return Err(Error::schema_mismatch(format!("field is neither fast nor indexed: {}", field)).with_path("field"));
+                }
+                // Not fast: match any term in the field's term dictionary.
+                let ty = match fd.field_type.as_str() {
+                    "text" => Type::Str,
+                    "i64" => Type::I64,
+                    "f64" => Type::F64,
+                    "bytes" => Type::Bytes,
+                    other => return Err(Error::schema_mismatch(format!("unsupported field type: {}", other)).with_path("field")),
+                };
+                let unbounded = std::ops::Bound::Unbounded;
+                Ok(Box::new(RangeQuery::new_term_bounds(field.clone(), ty, &unbounded, &unbounded)))
+            }
+            QueryDef::FunctionScore { .. } => {
+                Err(Error::bad_request("function_score is only supported at the root of a search request").with_path("type"))
+            }
+            QueryDef::MoreLikeThis { like_doc, like_text, id_field, fields, min_doc_frequency, min_term_frequency, max_query_terms, include } => {
+                if *max_query_terms == 0 {
+                    return Err(Error::bad_request("max_query_terms must be at least 1").with_path("max_query_terms"));
+                }
+                let fields = self.resolve_fields(fields)?;
+                // The similarity query has no per-field boosts.
+                self.warn_fields(cx, "more_like_this ignores the weights of search fields", fields.iter().filter(|(_, w)| *w != 1.0).map(|(f, _)| *f));
+                self.warn_bm25(cx, "more_like_this", fields.iter().map(|(f, _)| *f));
+                if like_doc.is_some() {
+                    let unstored = fields.iter().map(|(f, _)| *f).filter(|f| !self.schema.get_field_entry(*f).is_stored());
+                    self.warn_fields(cx, "more_like_this skips like_doc fields that aren't stored", unstored);
+                }
+                let (mut values, source): (Vec<(Field, Vec<OwnedValue>)>, _) = match (like_doc, like_text) {
+                    (Some(id), None) => {
+                        let t = self.term_for(id_field, id).map_err(|e| match e.path.as_deref() {
+                            Some("value") => e.with_path("like_doc"),
+                            _ => e.with_path("id_field"),
+                        })?;
+                        let top = cx.searcher.search(&TermQuery::new(t.clone(), IndexRecordOption::Basic), &TopDocs::with_limit(1))?;
+                        let Some(&(_, addr)) = top.first() else {
+                            return Err(Error::not_found(format!("no document with {} = {}", id_field, id)).with_path("like_doc"));
+                        };
+                        let doc: TantivyDocument = cx.searcher.doc(addr)?;
+                        (fields.iter().map(|(f, _)| (*f, doc.get_all(*f).cloned().collect())).collect(), Some(t))
+                    }
+                    (None, Some(text)) => (fields.iter().map(|(f, _)| (*f, vec![OwnedValue::Str(text.clone())])).collect(), None),
+                    _ => return Err(Error::bad_request("more_like_this needs exactly one of like_doc and like_text").with_path("like_doc")),
+                };
+                // Only stored values of `like_doc` are seen; with none, nothing is similar.
+                values.retain(|(_, v)| !v.is_empty());
+                if values.is_empty() {
+                    self.warn_fields(cx, "more_like_this matches nothing, the source has no values in", fields.iter().map(|(f, _)| *f));
+                    return Ok(Box::new(EmptyQuery));
+                }
+                let q: Box<dyn Query> = Box::new(SimilarQuery(MoreLikeThisQuery::builder()
+                    .with_min_doc_frequency(*min_doc_frequency)
+                    .with_min_term_frequency(*min_term_frequency)
+                    // tantivy keeps one term more than its limit.
+                    .with_max_query_terms(max_query_terms - 1)
+                    .with_document_fields(values)));
+                Ok(match source {
+                    Some(t) if !include => Box::new(BooleanQuery::new(vec![
+                        (Occur::Must, q),
+                        (Occur::MustNot, Box::new(TermQuery::new(t, IndexRecordOption::Basic))),
+                    ])),
+                    _ => q,
+                })
+            }
+            QueryDef::ConstantScore { query, score } => {
+                if !score.is_finite() || *score < 0.0 {
+                    return Err(Error::bad_request(format!("invalid score: {}", score)).with_path("score"));
+                }
+                let q = self.build_query(query, cx).map_err(|e| e.under("query"))?;
+                Ok(Box::new(ConstScoreQuery::new(q, *score)))
+            }
+            QueryDef::All => {
+                Ok(Box::new(tantivy::query::AllQuery))
+            }
+        }
+    }
+
+    /// Build child nodes stored under `key`, prefixing error paths with `key[i]`.
+    fn build_children(&self, key: &str, nodes: &[QueryNode], cx: &mut BuildContext) -> Result<Vec<Box<dyn Query>>, Error> {
+        nodes.iter().enumerate()
+            .map(|(i, n)| self.build_query(n, cx).map_err(|e| e.under(&format!("{}[{}]", key, i))))
+            .collect()
+    }
+
+    /// Schema fields a query tree refers to, with default search fields resolved.
+    fn collect_fields(&self, qd: &QueryDef, out: &mut BTreeSet<String>) {
+        let mut named = |fields: &[String]| {
+            for (f, _) in self.resolve_fields(fields).unwrap_or_default() { out.insert(self.schema.get_field_name(f).to_string()); }
+        };
+        match qd {
+            QueryDef::Text { fields, .. } | QueryDef::Fuzzy { fields, .. } | QueryDef::Phrase { fields, .. }
+            | QueryDef::Proximity { fields, .. } | QueryDef::Prefix { fields, .. }
+            | QueryDef::Regex { fields, .. } | QueryDef::Wildcard { fields, .. } => named(fields),
+            QueryDef::TermMatch { field, .. } | QueryDef::Terms { field, .. } | QueryDef::RangeI64 { field, .. }
+            | QueryDef::RangeF64 { field, .. } | QueryDef::Exists { field } => { out.insert(field.clone()); }
+            QueryDef::Bool { must, should, must_not, .. } => {
+                for n in must.iter().chain(should).chain(must_not) { self.collect_fields(&n.def, out); }
+            }
+            QueryDef::DisMax { queries, .. } => {
+                for n in queries { self.collect_fields(&n.def, out); }
+            }
+            QueryDef::MoreLikeThis { fields, id_field, like_doc, .. } => {
+                named(fields);
+                if like_doc.is_some() { out.insert(id_field.clone()); }
+            }
+            QueryDef::ConstantScore { query, .. } => self.collect_fields(&query.def, out),
+            QueryDef::FunctionScore { query, functions, .. } => {
+                self.collect_fields(&query.def, out);
+                out.extend(functions.iter().map(|f| f.field().to_string()));
+            }
+            QueryDef::All => {}
+        }
+    }
+
+    /// Swap the term leaves of a parsed text query for `Bm25TermQuery` where
+    /// `cx.bm25` has settings for their field, and apply field `weights` the
+    /// parser left out. Other leaves on such fields are noted in `cx.warnings`.
+    fn rescore(&self, q: Box<dyn Query>, cx: &mut BuildContext, weights: &HashMap<Field, Score>) -> Box<dyn Query> {
+        if cx.bm25.is_empty() { return q; }
+        if let Some(bq) = q.downcast_ref::<BooleanQuery>() {
+            let clauses = bq.clauses().iter().map(|(o, c)| (*o, self.rescore(c.box_clone(), cx, weights))).collect();
+            return Box::new(BooleanQuery::new(clauses));
+        }
+        let mut fields = Vec::new();
+        q.query_terms(&mut |t, _| fields.push(t.field()));
+        let q = match q.downcast_ref::<TermQuery>() {
+            Some(tq) => term_query(tq.term().clone(), IndexRecordOption::WithFreqs, &cx.bm25),
+            None => {
+                self.warn_bm25(cx, leaf_kind(&*q), fields.iter().copied());
+                q
+            }
+        };
+        match fields.first().and_then(|f| weights.get(f)) {
+            Some(w) => boosted(q, *w),
+            None => q,
+        }
+    }
+
+    /// Note that `kind` queries score `fields` without their BM25 settings.
+    fn warn_bm25(&self, cx: &mut BuildContext, kind: &str, fields: impl IntoIterator<Item = Field>) {
+        let fields: Vec<Field> = fields.into_iter().filter(|f| cx.bm25.contains_key(f)).collect();
+        self.warn_fields(cx, &format!("bm25 settings don't apply to {} queries", kind), fields);
+    }
+
+    /// Warn once with `what` followed by the names of `fields`, if any.
+    fn warn_fields(&self, cx: &mut BuildContext, what: &str, fields: impl IntoIterator<Item = Field>) {
+        let mut names: Vec<&str> = fields.into_iter().map(|f| self.schema.get_field_name(f)).collect();
+        if names.is_empty() { return; }
+        names.dedup();
+        let msg = format!("{}: {}", what, names.join(", "));
+        if !cx.warnings.contains(&msg) { cx.warnings.push(msg); }
+    }
+
+    fn check_field_type(&self, field: &str, ty: &str) -> Result<(), Error> {
+        match self.field_map.get(field) {
+            Some((_, fd)) if fd.field_type == ty => Ok(()),
+            Some(_) => Err(Error::schema_mismatch(format!("field is not {}: {}", ty, field)).with_path("field")),
+            None => Err(Error::schema_mismatch(format!("unknown field: {}", field)).with_path("field")),
+        }
+    }
+
+    /// Regex over each field's term dictionary. Unless `case_insensitive` says
+    /// otherwise, fields whose analyzer lowercases terms are matched
+    /// case-insensitively, so `CV-*` finds `cv-2023`, and `raw` fields exactly.
+    fn pattern_query(&self, pattern: &str, fields: &[String], case_insensitive: Option<bool>) -> Result<Box<dyn Query>, Error> {
+        let clauses = self.resolve_fields(fields)?.into_iter().map(|(fld, w)| {
+            let fd = self.field_map.values().find(|(f, _)| *f == fld).map(|(_, fd)| fd)
+                .ok_or_else(|| Error::internal("field not in field map"))?;
+            if fd.field_type != "text" {
+                return Err(Error::schema_mismatch(format!("not a text field: {}", fd.name)).with_path("fields"));
+            }
+            let pat = if case_insensitive.unwrap_or(fd.tokenizer != "raw") { format!("(?i){}", pattern) } else { pattern.to_string() };
+            let rq = RegexQuery::from_pattern(&pat, fld)
+                .map_err(|e| Error::bad_request(e.to_string()).with_path("pattern"))?;
+            Ok((Occur::Should, boosted(Box::new(rq), w)))
+        }).collect::<Result<Vec<_>, Error>>()?;
+        Ok(Box::new(BooleanQuery::new(clauses)))
+    }
+
+    /// Terms of `text` as the field's tokenizer produces them at index time.
+    fn analyze(&self, field: Field, text: &str) -> Result<Vec<tantivy::Term>, Error> {
+        let mut analyzer = self.index.tokenizer_for_field(field)?;
+        let mut stream = analyzer.token_stream(text);
+        let mut terms = Vec::new();
+        while stream.advance() { terms.push(tantivy::Term::from_field_text(field, &stream.token().text)); }
+        Ok(terms)
+    }
+
+    fn term_for(&self, field: &str, value: &serde_json::Value) -> Result<tantivy::Term, Error> {
+        let (fld, fd) = self.field_map.get(field)
+            .ok_or_else(|| Error::schema_mismatch(format!("unknown field: {}", field)).with_path("field"))?;
+        let mismatch = |want: &str| Error::bad_request(format!("field {} is {}, expected {} value, got {}", field, fd.field_type, want, value)).with_path("value");
+        match fd.field_type.as_str() {
+            "text" => value.as_str().map(|s| tantivy::Term::from_field_text(*fld, s)).ok_or_else(|| mismatch("a string")),
+            "i64" => value.as_i64().map(|v| tantivy::Term::from_field_i64(*fld, v)).ok_or_else(|| mismatch("an integer")),
+            "f64" => value.as_f64().map(|v| tantivy::Term::from_field_f64(*fld, v)).ok_or_else(|| mismatch("a number")),
+            "bytes" => {
+                let s = value.as_str().ok_or_else(|| mismatch("a base64 string"))?;
+                Ok(tantivy::Term::from_field_bytes(*fld, &decode_b64(s, "value")?))
+            }
+            _ => Err(Error::schema_mismatch("unsupported term type").with_path("field")),
+        }
+    }
+
+    fn resolve_fields(&self, names: &[String]) -> Result<Vec<(Field, Score)>, Error> {
+        if names.is_empty() {
+            Ok(self.search_fields.clone())
+        } else {
+            weighted_fields(names, &self.field_map, "fields")
+        }
+    }
+
+    /// Query parser over weighted fields, with per-field boosts applied.
+    fn parser(&self, fields: &[(Field, Score)]) -> QueryParser {
+        let mut qp = QueryParser::for_index(&self.index, fields.iter().map(|(f, _)| *f).collect());
+        for (f, w) in fields {
+            if *w != 1.0 { qp.set_field_boost(*f, *w); }
+        }
+        qp
+    }
+}
+
+/// Renders the hits of one search: stored fields, highlights, doc values
+/// and explanations.
+struct HitRenderer<'a> {
+    index: &'a TantivyIndex,
+    searcher: &'a Searcher,
+    stored: Vec<(&'a String, &'a Field, &'a FieldDef)>,
+    docvalues: Vec<(&'a String, &'a FieldDef)>,
+    snippets: Vec<(&'a String, SnippetGenerator)>,
+    explainer: Option<Box<dyn Weight>>,
+    functions: Option<Arc<FunctionScorer>>,
+    // Fast-field columns, opened lazily per segment.
+    columns: HashMap<u32, Vec<DocValueColumn>>,
+}
+
+impl HitRenderer<'_> {
+    fn render(&mut self, addr: DocAddress) -> Result<Hit, Error> {
+        let mut obj = Hit::new();
+        if !self.stored.is_empty() || !self.snippets.is_empty() {
+            let doc: TantivyDocument = self.searcher.doc(addr)?;
+            self.index.read_stored(&doc, &self.stored, &mut obj);
+            if !self.snippets.is_empty() {
+                let mut hl = Hit::new();
+                for (name, gen) in &self.snippets {
+                    let snippet = gen.snippet_from_doc(&doc);
+                    if !snippet.is_empty() { hl.insert((*name).clone(), HitValue::Str(snippet.to_html())); }
+                }
+                obj.insert("_highlight".to_string(), HitValue::Map(hl));
+            }
+        }
+        if !self.docvalues.is_empty() {
+            let cols = match self.columns.entry(addr.segment_ord) {
+                Entry::Occupied(e) => e.into_mut(),
+                Entry::Vacant(e) => {
+                    let ff = self.searcher.segment_reader(addr.segment_ord).fast_fields();
+                    let cols = self.docvalues.iter()
+                        .map(|(name, fd)| DocValueColumn::open(ff, name, fd))
+                        .collect::<Result<Vec<_>, Error>>()?;
+                    e.insert(cols)
+                }
+            };
+            for ((name, _), col) in self.docvalues.iter().zip(cols.iter()) {
+                if let Some(v) = col.first(addr.doc_id)? { obj.insert((*name).clone(), v); }
+            }
+        }
+        if let Some(w) = &self.explainer {
+            let reader = self.searcher.segment_reader(addr.segment_ord);
+            let mut expl = w.explain(reader, addr.doc_id)?;
+            if let Some(fs) = &self.functions { expl = fs.explain(expl, &fs.segment(reader), addr.doc_id); }
+            obj.insert("_explanation".to_string(), HitValue::Json(serde_json::to_value(&expl).map_err(|e| Error::internal(format!("explanation: {}", e)))?));
+        }
+        Ok(obj)
+    }
+}
+
+/// How `exec` runs its collectors.
+struct Collection {
+    scoring: bool,
+    min_score: Option<Score>,
+    /// Root `function_score`, whose final score `min_score` applies to.
+    functions: Option<Arc<FunctionScorer>>,
+}
+
+impl Collection {
+    fn new(req: &SearchRequest, functions: Option<Arc<FunctionScorer>>) -> Self {
+        Collection { scoring: req.scoring != "none", min_score: req.min_score, functions }
+    }
+
+    /// Run `collector`; without `scoring`, weights are built with scoring
+    /// disabled so scorers skip BM25 work entirely.
+    fn collect<C: Collector>(&self, searcher: &Searcher, query: &dyn Query, collector: &C) -> tantivy::Result<C::Fruit> {
+        if let Some(min) = self.min_score {
+            return searcher.search(query, &MinScoreCollector { inner: collector, min, functions: self.functions.clone() });
+        }
+        if self.scoring { return searcher.search(query, collector); }
+        searcher.search_with_executor(query, collector, searcher.index().search_executor(), EnableScoring::disabled_from_searcher(searcher))
+    }
+}
+
+/// Passes on only the hits scoring at least `min`, so counts, top docs and
+/// aggregations all ignore the rest.
+struct MinScoreCollector<'a, C> {
+    inner: &'a C,
+    min: Score,
+    functions: Option<Arc<FunctionScorer>>,
+}
+
+impl<C: Collector> Collector for MinScoreCollector<'_, C> {
+    type Fruit = C::Fruit;
+    type Child = MinScoreSegmentCollector<C::Child>;
+
+    fn for_segment(&self, segment_ord: u32, reader: &SegmentReader) -> tantivy::Result<Self::Child> {
+        Ok(MinScoreSegmentCollector {
+            inner: self.inner.for_segment(segment_ord, reader)?,
+            min: self.min,
+            functions: self.functions.as_ref().map(|fs| (fs.clone(), fs.segment(reader))),
+        })
+    }
+
+    fn requires_scoring(&self) -> bool { true }
+
+    fn merge_fruits(&self, fruits: Vec<<C::Child as SegmentCollector>::Fruit>) -> tantivy::Result<C::Fruit> {
+        self.inner.merge_fruits(fruits)
+    }
+}
+
+struct MinScoreSegmentCollector<S> {
+    inner: S,
+    min: Score,
+    functions: Option<(Arc<FunctionScorer>, Arc<Vec<DocValueColumn>>)>,
+}
+
+impl<S: SegmentCollector> SegmentCollector for MinScoreSegmentCollector<S> {
+    type Fruit = S::Fruit;
+
+    fn collect(&mut self, doc: tantivy::DocId, score: Score) {
+        let final_score = match &self.functions {
+            Some((fs, cols)) => fs.score(score, cols, doc),
+            None => score,
+        };
+        if final_score >= self.min { self.inner.collect(doc, score); }
+    }
+
+    fn harvest(self) -> S::Fruit { self.inner.harvest() }
+}
+
+/// A fast-field column opened for one segment, used by `docvalue_fields`.
+enum DocValueColumn {
+    I64(Column<i64>),
+    F64(Column<f64>),
+    Str(Option<StrColumn>),
+    Bytes(Option<BytesColumn>),
+}
+
+impl DocValueColumn {
+    fn open(ff: &tantivy::fastfield::FastFieldReaders, name: &str, fd: &FieldDef) -> Result<Self, Error> {
+        Ok(match fd.field_type.as_str() {
+            "i64" => DocValueColumn::I64(ff.i64(name)?),
+            "f64" => DocValueColumn::F64(ff.f64(name)?),
+            "text" => DocValueColumn::Str(ff.str(name)?),
+            "bytes" => DocValueColumn::Bytes(ff.bytes(name)?),
+            other => return Err(Error::schema_mismatch(format!("unsupported field type: {}", other))),
+        })
+    }
+
+    /// First value of a numeric column as f64, or `None` if the document has
+    /// no value.
+    fn number(&self, doc: tantivy::DocId) -> Option<f64> {
+        match self {
+            DocValueColumn::I64(c) => c.first(doc).map(|n| n as f64),
+            DocValueColumn::F64(c) => c.first(doc),
+            _ => None,
+        }
+    }
+
+    /// First value of `doc`, or `None` if the document has no value.
+    fn first(&self, doc: tantivy::DocId) -> Result<Option<HitValue>, Error> {
+        let mut buf = Vec::new();
+        Ok(match self {
+            DocValueColumn::I64(c) => c.first(doc).map(HitValue::I64),
+            DocValueColumn::F64(c) => c.first(doc).map(HitValue::F64),
+            DocValueColumn::Str(Some(c)) => match c.term_ords(doc).next() {
+                Some(ord) => {
+                    let mut s = String::new();
+                    c.ord_to_str(ord, &mut s)?;
+                    Some(HitValue::Str(s))
+                }
+                None => None,
+            },
+            DocValueColumn::Bytes(Some(c)) => match c.term_ords(doc).next() {
+                Some(ord) => {
+                    c.ord_to_bytes(ord, &mut buf)?;
+                    Some(HitValue::Str(B64.encode(&buf)))
+                }
+                None => None,
+            },
+            DocValueColumn::Str(None) | DocValueColumn::Bytes(None) => None,
+        })
+    }
+}
+
+/// A root `function_score` with its fast-field columns opened for every
+/// segment of the searcher.
+struct FunctionScorer {
+    functions: Vec<ScoreFunction>,
+    score_mode: String,
+    boost_mode: String,
+    columns: HashMap<SegmentId, Arc<Vec<DocValueColumn>>>,
+}
+
+impl FunctionScorer {
+    /// `None` unless `def` is a `function_score`.
+    fn open(searcher: &Searcher, def: &QueryDef, fm: &FieldMap) -> Result<Option<Self>, Error> {
+        let QueryDef::FunctionScore { functions, score_mode, boost_mode, .. } = def else { return Ok(None) };
+        let mut columns = HashMap::new();
+        for reader in searcher.segment_readers() {
+            let cols = functions.iter().map(|f| {
+                let (_, fd) = fm.get(f.field()).ok_or_else(|| Error::internal("field not in field map"))?;
+                DocValueColumn::open(reader.fast_fields(), f.field(), fd)
+            }).collect::<Result<Vec<_>, Error>>()?;
+            columns.insert(reader.segment_id(), Arc::new(cols));
+        }
+        Ok(Some(FunctionScorer { functions: functions.clone(), score_mode: score_mode.clone(), boost_mode: boost_mode.clone(), columns }))
+    }
+
+    fn segment(&self, reader: &SegmentReader) -> Arc<Vec<DocValueColumn>> {
+        self.columns.get(&reader.segment_id()).cloned().unwrap_or_default()
+    }
+
+    fn score(&self, score: Score, cols: &[DocValueColumn], doc: tantivy::DocId) -> Score {
+        let values = self.functions.iter().zip(cols).filter_map(|(f, c)| f.eval(c.number(doc)));
+        let (mut n, mut acc) = (0, match self.score_mode.as_str() { "multiply" => 1.0, "max" => f64::MIN, "min" => f64::MAX, _ => 0.0 });
+        for v in values {
+            n += 1;
+            acc = match self.score_mode.as_str() { "multiply" => acc * v, "max" => acc.max(v), "min" => acc.min(v), _ => acc + v };
+        }
+        // No function applies (every field missing): keep the query score.
+        if n == 0 { return score; }
+        if self.score_mode == "avg" { acc /= n as f64; }
+        let s = score as f64;
+        (match self.boost_mode.as_str() { "sum" => s + acc, "replace" => acc, _ => s * acc }) as Score
+    }
+
+    /// Wrap the inner query's explanation with the function values.
+    fn explain(&self, inner: Explanation, cols: &[DocValueColumn], doc: tantivy::DocId) -> Explanation {
+        let desc = format!("function_score, score_mode={}, boost_mode={}", self.score_mode, self.boost_mode);
+        let mut expl = Explanation::new_with_string(desc, self.score(inner.value(), cols, doc));
+        expl.add_detail(inner);
+        for (f, c) in self.functions.iter().zip(cols) {
+            if let Some(v) = f.eval(c.number(doc)) {
+                expl.add_detail(Explanation::new_with_string(format!("{}({}), weight={}", f.name(), f.field(), f.weight), v as Score));
+            }
+        }
+        expl
+    }
+}
+
+/// Resolve field names with optional `^weight` suffixes. Names must be in
+/// the schema and weights finite and non-negative; errors point at `key[i]`.
+fn weighted_fields(names: &[String], fm: &FieldMap, key: &str) -> Result<Vec<(Field, Score)>, Error> {
+    let mut out = Vec::with_capacity(names.len());
+    for (i, n) in names.iter().enumerate() {
+        let (name, w) = match n.rsplit_once('^') {
+            Some((name, w)) => match w.parse::<Score>() {
+                Ok(w) if w.is_finite() && w >= 0.0 => (name, w),
+                _ => return Err(Error::bad_request(format!("invalid field weight: {}", n)).with_path(format!("{}[{}]", key, i))),
+            },
+            None => (n.as_str(), 1.0),
+        };
+        match fm.get(name) {
+            Some((f, _)) => out.push((*f, w)),
+            None => return Err(Error::schema_mismatch(format!("unknown field: {}", name)).with_path(format!("{}[{}]", key, i))),
+        }
+    }
+    Ok(out)
+}
+
+/// Apply a node's `boost`, rejecting negative and non-finite values.
+fn with_boost(q: Box<dyn Query>, boost: Option<Score>) -> Result<Box<dyn Query>, Error> {
+    match boost {
+        Some(b) if !b.is_finite() || b < 0.0 => Err(Error::bad_request(format!("invalid boost: {}", b)).with_path("boost")),
+        Some(b) => Ok(boosted(q, b)),
+        None => Ok(q),
+    }
+}
+
+fn boosted(q: Box<dyn Query>, boost: Score) -> Box<dyn Query> {
+    if boost == 1.0 { q } else { Box::new(BoostQuery::new(q, boost)) }
+}
+
+/// A term query, scored with the field's BM25 settings if `bm25` has them.
+fn term_query(t: tantivy::Term, option: IndexRecordOption, bm25: &HashMap<Field, (Score, Score)>) -> Box<dyn Query> {
+    match bm25.get(&t.field()) {
+        Some(&(k1, b)) => Box::new(Bm25TermQuery::new(t, option, k1, b)),
+        None => Box::new(TermQuery::new(t, option)),
+    }
+}
+
+/// What a parsed text query leaf is, for warnings.
+fn leaf_kind(q: &dyn Query) -> &'static str {
+    if q.downcast_ref::<PhraseQuery>().is_some() { return "phrase"; }
+    if q.downcast_ref::<BoostQuery>().is_some() { return "boosted (term^n)"; }
+    if q.downcast_ref::<FuzzyTermQuery>().is_some() { return "fuzzy"; }
+    "text"
+}
+
+const B64: base64::engine::GeneralPurpose = base64::engine::general_purpose::STANDARD;
+
+fn decode_b64(s: &str, path: &str) -> Result<Vec<u8>, Error> {
+    B64.decode(s).map_err(|e| Error::bad_request(format!("base64: {}", e)).with_path(path))
+}
+
+/// Longest regex/wildcard pattern accepted.
+const MAX_PATTERN_LEN: usize = 256;
+/// Largest number of copies counted repetitions (`{n}`, `{n,m}`) may make of
+/// any part of a regex. Nested counts multiply: `(a{8}){8}` makes 64.
+const MAX_REPEAT: u32 = 64;
+
+/// Reject patterns likely to build huge automata; tantivy's own size limits
+/// still apply on top of this. Length is checked on the pattern as written,
+/// repetition counts on `regex`, the regex it translates to.
+fn check_pattern(pattern: &str, regex: &str) -> Result<(), Error> {
+    let bad = |msg: String| Err(Error::bad_request(msg).with_path("pattern"));
+    if pattern.is_empty() { return bad("empty pattern".to_string()); }
+    if pattern.len() > MAX_PATTERN_LEN {
+        return bad(format!("pattern longer than {} bytes", MAX_PATTERN_LEN));
+    }
+    let hir = match regex_syntax::Parser::new().parse(regex) {
+        Ok(hir) => hir,
+        Err(e) => return bad(format!("invalid pattern: {}", e)),
+    };
+    if repeat_copies(&hir) > MAX_REPEAT as u64 {
+        return bad(format!("repetition count above {}, counting nested repetitions together", MAX_REPEAT));
+    }
+    Ok(())
+}
+
+/// Most copies counted repetitions make of any single part of `hir`: the
+/// product of the counts it is nested in. Open-ended repeats (`*`, `+`,
+/// `{n,}`) loop rather than copy, so they count their minimum.
+fn repeat_copies(hir: &regex_syntax::hir::Hir) -> u64 {
+    use regex_syntax::hir::HirKind;
+    match hir.kind() {
+        HirKind::Repetition(r) => {
+            let n = r.max.unwrap_or(r.min).max(1) as u64;
+            n.saturating_mul(repeat_copies(&r.sub))
+        }
+        HirKind::Capture(c) => repeat_copies(&c.sub),
+        HirKind::Concat(subs) | HirKind::Alternation(subs) => subs.iter().map(repeat_copies).max().unwrap_or(1),
+        _ => 1,
+    }
+}
+
+fn wildcard_to_regex(pattern: &str) -> String {
+    let mut o = String::with_capacity(pattern.len() * 2);
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => o.push_str(".*"),
+            '?' => o.push('.'),
+            '\\' => if let Some(n) = chars.next() { o.push_str(&regex_escape(&n.to_string())); },
+            c => o.push_str(&regex_escape(&c.to_string())),
+        }
+    }
+    o
+}
+
+fn regex_escape(s: &str) -> String {
+    let mut o = String::with_capacity(s.len() * 2);
+    for c in s.chars() {
+        if "\\.*+?()[]{}^$|".contains(c) { o.push('\\'); }
+        o.push(c);
+    }
+    o
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempIndex;
+
+    #[test]
+    fn legacy_flat_request() {
+        let req = SearchRequest::parse(r#"{"type":"all","limit":10}"#).unwrap();
+        assert!(matches!(req.query.def, QueryDef::All));
+        assert!(req.flat);
+        assert_eq!((req.limit, req.offset), (10, 0));
+        assert!(req.warnings.is_empty());
+
+        let req = SearchRequest::parse(r#"{"type":"all","return_fields":["id"],"count_only":true}"#).unwrap();
+        assert_eq!(req.fields, ["id"]);
+        assert!(req.count_only);
+    }
+
+    #[test]
+    fn envelope_accepts_return_fields() {
+        let req = SearchRequest::parse(r#"{"query":{"type":"all"},"return_fields":["id","title"]}"#).unwrap();
+        assert_eq!(req.fields, ["id", "title"]);
+        assert!(!req.flat);
+    }
+
+    /// The `id` of each hit, best first.
+    fn ids(r: &SearchResults) -> Vec<&str> {
+        r.results.iter().map(|h| match h.get("id") { Some(HitValue::Str(s)) => s.as_str(), v => panic!("id: {:?}", v) }).collect()
+    }
+
+    fn codes_index() -> TempIndex {
+        let codes = ["v1.2+(x)", "v1x2+(x)", "v1.22(x)", "abc", "abbc", "ac", "xabc"];
+        let docs: Vec<_> = codes.iter().map(|code| serde_json::json!({ "id": code })).collect();
+        TempIndex::with_docs(r#"{"fields":[{"name":"id","type":"text","tokenizer":"raw"}]}"#, &docs)
+    }
+
+    fn wildcard(idx: &TantivyIndex, pattern: &str) -> Vec<String> {
+        let q = serde_json::json!({"type":"wildcard","pattern":pattern,"fields":["id"],"limit":10});
+        let mut found: Vec<String> = ids(&idx.search(&q.to_string()).unwrap()).into_iter().map(String::from).collect();
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn wildcard_metacharacters_are_literal() {
+        assert_eq!(wildcard_to_regex("v1.2+(x)"), r"v1\.2\+\(x\)");
+        assert_eq!(wildcard_to_regex(r"a\*b"), r"a\*b");
+        let idx = codes_index();
+        assert_eq!(wildcard(&idx, "v1.2+(x)"), ["v1.2+(x)"]);
+        assert_eq!(wildcard(&idx, "v1.2*"), ["v1.2+(x)", "v1.22(x)"]);
+    }
+
+    #[test]
+    fn wildcard_expands_question_and_star() {
+        assert_eq!(wildcard_to_regex("a?c*"), "a.c.*");
+        let idx = codes_index();
+        assert_eq!(wildcard(&idx, "a?c"), ["abc"]);
+        assert_eq!(wildcard(&idx, "a*c"), ["abbc", "abc", "ac"]);
+        assert_eq!(wildcard(&idx, "*bc"), ["abbc", "abc", "xabc"]);
+    }
+
+    #[test]
+    fn pattern_length_limit() {
+        let max = "a".repeat(MAX_PATTERN_LEN);
+        assert!(check_pattern(&max, &max).is_ok());
+        let long = "a".repeat(MAX_PATTERN_LEN + 1);
+        let e = check_pattern(&long, &long).unwrap_err();
+        assert_eq!((e.kind, e.path.as_deref()), (ErrorKind::BadRequest, Some("pattern")));
+        assert!(check_pattern("", "").is_err());
+    }
+
+    #[test]
+    fn large_repetition_rejected() {
+        for p in ["a{65}", "a{2,1000}", "(ab{1,100})+", "(a{3}){100}", "a{65,}"] {
+            let e = check_pattern(p, p).unwrap_err();
+            assert_eq!((e.kind, e.path.as_deref()), (ErrorKind::BadRequest, Some("pattern")), "{}", p);
+        }
+        // Escaped braces and braces in a class are literals, not counts.
+        for p in [r"a\{100\}", "[{]100}", "a{64}"] { assert!(check_pattern(p, p).is_ok(), "{}", p); }
+
+        let idx = codes_index();
+        let q = r#"{"type":"regex","pattern":"((a{64}){64}){64}","fields":["id"]}"#;
+        let e = idx.search(q).unwrap_err();
+        assert_eq!((e.kind, e.path.as_deref()), (ErrorKind::BadRequest, Some("pattern")));
+        // A wildcard's `{` is escaped, so it is never read as a count.
+        assert!(wildcard(&idx, "a{1000}").is_empty());
+    }
+
+    #[test]
+    fn nested_repetitions_multiply() {
+        for p in ["((a{64}){64}){64}", "(a{10}){10}", "((ab){4}c{2}){20}", "(x|(a{9}){8})+"] {
+            let e = check_pattern(p, p).unwrap_err();
+            assert_eq!((e.kind, e.path.as_deref()), (ErrorKind::BadRequest, Some("pattern")), "{}", p);
+        }
+        // Products up to the limit pass; open-ended repeats don't multiply.
+        for p in ["(a{8}){8}", "((a{4}){4}){4}", "(a{64})+", "((a+)*b{2}){32}", "a{64}b{64}"] {
+            assert!(check_pattern(p, p).is_ok(), "{}", p);
+        }
+    }
+
+    #[test]
+    fn dis_max_tie_breaker_range() {
+        let idx = codes_index();
+        let q = |tb: f64| serde_json::json!({"type":"dis_max","tie_breaker":tb,"queries":[{"type":"wildcard","pattern":"a*","fields":["id"]}]}).to_string();
+        for tb in [0.0, 0.3, 1.0] { assert!(idx.search(&q(tb)).is_ok(), "{}", tb); }
+        for tb in [-0.1, 1.5] {
+            let e = idx.search(&q(tb)).unwrap_err();
+            assert_eq!((e.kind, e.path.as_deref()), (ErrorKind::BadRequest, Some("tie_breaker")), "{}", tb);
+        }
+        let e = idx.search(&format!(r#"{{"query":{},"limit":1}}"#, q(2.0))).unwrap_err();
+        assert_eq!(e.path.as_deref(), Some("query.tie_breaker"));
+    }
+
+    fn fruit_index(bm25: &str) -> TempIndex {
+        let schema = format!(
+            r#"{{"fields":[{{"name":"id","type":"text","tokenizer":"raw"}},{{"name":"body","type":"text","stored":false{}}}]}}"#,
+            bm25,
+        );
+        let docs = [
+            serde_json::json!({"id": "short", "body": "apple pear"}),
+            serde_json::json!({"id": "long", "body": "apple apple apple x x x x x x x x x x x"}),
+        ];
+        TempIndex::with_docs(&schema, &docs)
+    }
+
+    #[test]
+    fn bm25_from_schema_and_request() {
+        let search = |idx: &TantivyIndex, bm25: &str| {
+            let q = format!(r#"{{"query":{{"type":"text","query":"apple","fields":["body"]}}{}}}"#, bm25);
+            ids(&idx.search(&q).unwrap()).into_iter().map(String::from).collect::<Vec<_>>()
+        };
+        // Full length normalization favours the short document...
+        let plain = fruit_index("");
+        assert_eq!(search(&plain, ""), ["short", "long"]);
+        // ...none, set on the schema field or by the request, lets term frequency win.
+        let flat = fruit_index(r#","bm25":{"b":0.0}"#);
+        assert_eq!(search(&flat, ""), ["long", "short"]);
+        assert_eq!(search(&plain, r#","bm25":{"fields":{"body":{"b":0.0}}}"#), ["long", "short"]);
+        // The request overrides the schema.
+        assert_eq!(search(&flat, r#","bm25":{"b":0.75}"#), ["short", "long"]);
+    }
+
+    #[test]
+    fn bm25_rejects_out_of_range() {
+        let dir = tempfile::TempDir::new().unwrap();
+        for (bm25, path) in [(r#"{"b":1.5}"#, "fields.body.bm25.b"), (r#"{"k1":-1}"#, "fields.body.bm25.k1")] {
+            let schema = format!(r#"{{"fields":[{{"name":"body","type":"text","bm25":{}}}]}}"#, bm25);
+            let e = TantivyIndex::create(dir.path().to_str().unwrap(), &schema).err().unwrap();
+            assert_eq!((e.kind, e.path.as_deref()), (ErrorKind::BadRequest, Some(path)));
+        }
+
+        let idx = fruit_index("");
+        for (bm25, path) in [(r#"{"b":1.5}"#, "bm25.b"), (r#"{"fields":{"body":{"k1":-1}}}"#, "bm25.fields.body.k1")] {
+            let q = format!(r#"{{"query":{{"type":"all"}},"bm25":{}}}"#, bm25);
+            let e = idx.search(&q).unwrap_err();
+            assert_eq!((e.kind, e.path.as_deref()), (ErrorKind::BadRequest, Some(path)));
+        }
+    }
+
+    fn apple_results() -> SearchResults {
+        let idx = fruit_index("");
+        idx.search(r#"{"query":{"type":"text","query":"apple","fields":["body"]}}"#).unwrap()
+    }
+
+    #[test]
+    fn json_scores_stay_f32() {
+        let r = apple_results();
+        let Some(HitValue::F32(score)) = r.results[0].get("_score") else { panic!("{:?}", r.results[0]) };
+        let json: serde_json::Value = serde_json::from_slice(&ResultEncoding::Json.encode(&r).unwrap()).unwrap();
+        // Printed at f32 precision, so it parses back to the same f32.
+        let text = json["results"][0]["_score"].to_string();
+        assert_eq!(text, score.to_string());
+        assert_eq!(text.parse::<f32>().unwrap(), *score);
+    }
+
+    #[test]
+    fn msgpack_encodes_hits_as_maps() {
+        let r = apple_results();
+        let bytes = ResultEncoding::MsgPack.encode(&r).unwrap();
+        let decoded: serde_json::Value = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(decoded["count"], 2);
+        assert_eq!(decoded["total_count"], 2);
+        assert_eq!(decoded["results"][0]["id"], "short");
+        assert_eq!(decoded["results"][1]["id"], "long");
+        let Some(HitValue::F32(score)) = r.results[0].get("_score") else { panic!() };
+        assert_eq!(decoded["results"][0]["_score"].as_f64(), Some(*score as f64));
+        // Scores go out as MessagePack float32 (0xca) followed by the f32 bits.
+        let mut marker = vec![0xca];
+        marker.extend(score.to_be_bytes());
+        assert!(bytes.windows(5).any(|w| w == marker));
+
+        let back: SearchResults = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(ids(&back), ["short", "long"]);
+    }
+
+    #[test]
+    fn columnar_aligns_columns_by_hit() {
+        let hit = |fields: &[(&str, HitValue)]| fields.iter().map(|(k, v)| (k.to_string(), v.clone())).collect::<Hit>();
+        let r = SearchResults {
+            results: vec![
+                hit(&[("id", HitValue::Str("a".into())), ("n", HitValue::I64(1))]),
+                hit(&[("id", HitValue::Str("b".into()))]),
+                hit(&[("id", HitValue::Str("c".into())), ("x", HitValue::F64(0.5))]),
+            ],
+            count: 3, total_count: 5, limit: 3, offset: 0,
+            total_groups: None, aggregations: None, warnings: vec!["w".into()],
+        };
+        let bytes = ResultEncoding::MsgPackColumnar.encode(&r).unwrap();
+        let c: ColumnarResults = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(c.columns.keys().collect::<Vec<_>>(), ["id", "n", "x"]);
+        assert_eq!(c.columns["id"], [HitValue::Str("a".into()), HitValue::Str("b".into()), HitValue::Str("c".into())]);
+        assert_eq!(c.columns["n"], [HitValue::I64(1), HitValue::Null, HitValue::Null]);
+        assert_eq!(c.columns["x"], [HitValue::Null, HitValue::Null, HitValue::F64(0.5)]);
+        assert_eq!((c.count, c.total_count, c.limit, c.offset), (3, 5, 3, 0));
+        assert_eq!(c.warnings, ["w"]);
+
+        // A real search: one column per returned field plus _score.
+        let c: ColumnarResults = rmp_serde::from_slice(&ResultEncoding::MsgPackColumnar.encode(&apple_results()).unwrap()).unwrap();
+        assert_eq!(c.columns.keys().collect::<Vec<_>>(), ["_score", "id"]);
+        assert_eq!(c.columns["id"], [HitValue::Str("short".into()), HitValue::Str("long".into())]);
+    }
+
+    /// `rust` is in four documents, `tantivy` in two.
+    fn similar_index() -> TempIndex {
+        let docs = [("src", "rust rust tantivy tantivy"), ("a", "rust tantivy"), ("b", "rust"), ("e", "rust cargo"), ("d", "python")];
+        let docs: Vec<_> = docs.iter().map(|(id, body)| serde_json::json!({ "id": id, "body": body })).collect();
+        let schema = r#"{"fields":[{"name":"id","type":"text","tokenizer":"raw"},{"name":"body","type":"text"}],"search_fields":["body^2"]}"#;
+        TempIndex::with_docs(schema, &docs)
+    }
+
+    fn similar(idx: &TantivyIndex, options: serde_json::Value) -> SearchResults {
+        let mut q = serde_json::json!({"type": "more_like_this", "limit": 10});
+        q.as_object_mut().unwrap().extend(options.as_object().unwrap().clone());
+        idx.search(&q.to_string()).unwrap()
+    }
+
+    fn sorted_ids(r: &SearchResults) -> Vec<&str> {
+        let mut found = ids(r);
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn more_like_this_like_doc_and_like_text() {
+        let idx = similar_index();
+        let r = similar(&idx, serde_json::json!({"like_doc": "src", "min_doc_frequency": 1}));
+        assert_eq!(ids(&r)[0], "a");
+        assert_eq!(sorted_ids(&r), ["a", "b", "e"]);
+        let r = similar(&idx, serde_json::json!({"like_doc": "src", "min_doc_frequency": 1, "include": true}));
+        assert_eq!(sorted_ids(&r), ["a", "b", "e", "src"]);
+        let r = similar(&idx, serde_json::json!({"like_text": "tantivy tantivy", "min_doc_frequency": 1}));
+        assert_eq!(sorted_ids(&r), ["a", "src"]);
+
+        let e = idx.search(r#"{"type":"more_like_this","like_doc":"nope"}"#).unwrap_err();
+        assert_eq!((e.kind, e.path.as_deref()), (ErrorKind::NotFound, Some("like_doc")));
+        let e = idx.search(r#"{"type":"more_like_this","like_doc":"src","like_text":"rust"}"#).unwrap_err();
+        assert_eq!((e.kind, e.path.as_deref()), (ErrorKind::BadRequest, Some("like_doc")));
+    }
+
+    #[test]
+    fn more_like_this_min_doc_frequency() {
+        let idx = similar_index();
+        // At the default of 5 neither term is common enough; at 3 only `rust` is.
+        assert!(similar(&idx, serde_json::json!({"like_doc": "src"})).results.is_empty());
+        let r = similar(&idx, serde_json::json!({"like_doc": "src", "min_doc_frequency": 3}));
+        assert_eq!(sorted_ids(&r), ["a", "b", "e"]);
+        let r = similar(&idx, serde_json::json!({"like_text": "tantivy tantivy", "min_doc_frequency": 3}));
+        assert!(r.results.is_empty());
+    }
+
+    #[test]
+    fn more_like_this_max_query_terms() {
+        let idx = similar_index();
+        let text = serde_json::json!("rust rust tantivy tantivy");
+        // The rarer `tantivy` is the more significant term.
+        let r = similar(&idx, serde_json::json!({"like_text": text, "min_doc_frequency": 1, "max_query_terms": 1}));
+        assert_eq!(sorted_ids(&r), ["a", "src"]);
+        let r = similar(&idx, serde_json::json!({"like_text": text, "min_doc_frequency": 1, "max_query_terms": 2}));
+        assert_eq!(sorted_ids(&r), ["a", "b", "e", "src"]);
+
+        let e = idx.search(r#"{"type":"more_like_this","like_text":"rust","max_query_terms":0}"#).unwrap_err();
+        assert_eq!((e.kind, e.path.as_deref()), (ErrorKind::BadRequest, Some("max_query_terms")));
+    }
+
+    #[test]
+    fn more_like_this_ignores_field_weights() {
+        let idx = similar_index();
+        let warning = "more_like_this ignores the weights of search fields: body";
+        // Weights from the default search fields and from `fields` alike.
+        for options in [serde_json::json!({}), serde_json::json!({"fields": ["body^3"]})] {
+            let mut options = options;
+            options.as_object_mut().unwrap().extend([("like_doc".to_string(), "src".into()), ("min_doc_frequency".to_string(), 1.into())]);
+            let r = similar(&idx, options);
+            assert_eq!(sorted_ids(&r), ["a", "b", "e"]);
+            assert_eq!(r.warnings, [warning]);
+        }
+        let r = similar(&idx, serde_json::json!({"like_doc": "src", "min_doc_frequency": 1, "fields": ["body"]}));
+        assert!(r.warnings.is_empty(), "{:?}", r.warnings);
+    }
+
+    fn films_index() -> TempIndex {
+        let schema = r#"{"fields":[
+            {"name":"id","type":"text","tokenizer":"raw"},
+            {"name":"title","type":"text"},
+            {"name":"plot","type":"text","stored":false},
+            {"name":"year","type":"i64","fast":true},
+            {"name":"rating","type":"f64","fast":true,"stored":false}
+        ]}"#;
+        let docs = [
+            serde_json::json!({"id": "a", "title": "alpha", "plot": "x", "year": 1999, "rating": 8.5}),
+            serde_json::json!({"id": "b", "title": "beta", "plot": "y", "year": 2004}),
+        ];
+        TempIndex::with_docs(schema, &docs)
+    }
+
+    /// Each hit's keys, for a search sorted by year.
+    fn projected(idx: &TantivyIndex, options: &str) -> Vec<Vec<String>> {
+        let q = format!(r#"{{"query":{{"type":"all"}},"sort":{{"field":"year","order":"asc"}}{}}}"#, options);
+        idx.search(&q).unwrap().results.iter().map(|h| h.keys().cloned().collect()).collect()
+    }
+
+    #[test]
+    fn projection_picks_stored_and_fast_fields() {
+        let idx = films_index();
+        assert_eq!(projected(&idx, ""), [["_sort", "id", "title", "year"]; 2]);
+        assert_eq!(projected(&idx, r#","fields":["id"]"#), [["_sort", "id"]; 2]);
+        assert_eq!(projected(&idx, r#","exclude_fields":["title","year"]"#), [["_sort", "id"]; 2]);
+        assert_eq!(projected(&idx, r#","fields":["id","title"],"exclude_fields":["title"]"#), [["_sort", "id"]; 2]);
+        // Fast fields alone skip stored fields; a document without a value leaves it out.
+        assert_eq!(projected(&idx, r#","docvalue_fields":["rating"]"#), [vec!["_sort", "rating"], vec!["_sort"]]);
+        assert_eq!(projected(&idx, r#","fields":["id"],"docvalue_fields":["rating"]"#), [vec!["_sort", "id", "rating"], vec!["_sort", "id"]]);
+
+        let r = idx.search(r#"{"query":{"type":"all"},"fields":["id"],"docvalue_fields":["year","rating"],"sort":{"field":"year","order":"asc"}}"#).unwrap();
+        assert_eq!(r.results[0]["year"], HitValue::I64(1999));
+        assert_eq!(r.results[0]["rating"], HitValue::F64(8.5));
+        assert_eq!(r.results[1]["year"], HitValue::I64(2004));
+        // The flat form reads return_fields.
+        let r = idx.search(r#"{"type":"all","return_fields":["title"],"exclude_fields":["id"]}"#).unwrap();
+        assert!(r.results.iter().all(|h| h.keys().eq(["_score", "title"])));
+    }
+
+    #[test]
+    fn projection_rejects_unusable_fields() {
+        let idx = films_index();
+        for (options, path, message) in [
+            (r#""fields":["plot"]"#, "fields[0]", "field is not stored: plot"),
+            (r#""fields":["id","nope"]"#, "fields[1]", "unknown field: nope"),
+            (r#""exclude_fields":["rating"]"#, "exclude_fields[0]", "field is not stored: rating"),
+            (r#""docvalue_fields":["title"]"#, "docvalue_fields[0]", "field is not fast: title"),
+            (r#""docvalue_fields":["year","nope"]"#, "docvalue_fields[1]", "unknown field: nope"),
+        ] {
+            let e = idx.search(&format!(r#"{{"query":{{"type":"all"}},{}}}"#, options)).unwrap_err();
+            assert_eq!((e.kind, e.path.as_deref(), e.message.as_str()), (ErrorKind::SchemaMismatch, Some(path), message));
+        }
+        let e = idx.search(r#"{"type":"all","return_fields":["plot"]}"#).unwrap_err();
+        assert_eq!(e.path.as_deref(), Some("return_fields[0]"));
+    }
+
+    /// `t` has "batman" in its title, `p` in its plot.
+    fn batman_index(search_fields: &str) -> TempIndex {
+        let schema = format!(
+            r#"{{"fields":[{{"name":"id","type":"text","tokenizer":"raw"}},{{"name":"title","type":"text"}},{{"name":"plot","type":"text"}}],"search_fields":{}}}"#,
+            search_fields,
+        );
+        let docs = [
+            serde_json::json!({"id": "t", "title": "batman", "plot": "a bat"}),
+            serde_json::json!({"id": "p", "title": "a bat", "plot": "batman"}),
+        ];
+        TempIndex::with_docs(&schema, &docs)
+    }
+
+    /// Score of each hit by id.
+    fn scores(idx: &TantivyIndex, query: serde_json::Value) -> BTreeMap<String, Score> {
+        let r = idx.search(&serde_json::json!({ "query": query }).to_string()).unwrap();
+        r.results.iter().map(|h| match (&h["id"], &h["_score"]) {
+            (HitValue::Str(id), HitValue::F32(s)) => (id.clone(), *s),
+            v => panic!("{:?}", v),
+        }).collect()
+    }
+
+    fn close(a: Score, b: Score) -> bool { (a - b).abs() <= 1e-5 * a.abs().max(b.abs()) }
+
+    #[test]
+    fn node_boost_multiplies_scores() {
+        let idx = batman_index(r#"["title"]"#);
+        let term = serde_json::json!({"type": "term_match", "field": "title", "value": "batman"});
+        let plain = scores(&idx, term.clone())["t"];
+        let mut boosted = term.clone();
+        boosted["boost"] = 2.5.into();
+        assert!(close(scores(&idx, boosted.clone())["t"], 2.5 * plain));
+        // A zero boost still matches; a nested boost scales its clause.
+        let mut zero = term.clone();
+        zero["boost"] = 0.into();
+        assert_eq!(scores(&idx, zero)["t"], 0.0);
+        let both = scores(&idx, serde_json::json!({"type": "bool", "should": [boosted, {"type": "term_match", "field": "plot", "value": "batman"}]}));
+        let plot = scores(&idx, serde_json::json!({"type": "term_match", "field": "plot", "value": "batman"}))["p"];
+        assert!(close(both["t"], 2.5 * plain) && close(both["p"], plot), "{:?}", both);
+
+        for (query, path) in [
+            (serde_json::json!({"type": "all", "boost": -1}), "query.boost"),
+            (serde_json::json!({"type": "bool", "must": [{"type": "all", "boost": -0.5}]}), "query.must[0].boost"),
+        ] {
+            let e = idx.search(&serde_json::json!({ "query": query }).to_string()).unwrap_err();
+            assert_eq!((e.kind, e.path.as_deref()), (ErrorKind::BadRequest, Some(path)));
+        }
+    }
+
+    #[test]
+    fn field_weights_scale_field_scores() {
+        let idx = batman_index(r#"["title","plot"]"#);
+        let text = |fields: serde_json::Value| scores(&idx, serde_json::json!({"type": "text", "query": "batman", "fields": fields}));
+        let even = text(serde_json::json!(["title", "plot"]));
+        let title = text(serde_json::json!(["title^3", "plot"]));
+        assert!(close(title["t"], 3.0 * even["t"]) && close(title["p"], even["p"]), "{:?} {:?}", even, title);
+        let plot = text(serde_json::json!(["title", "plot^0.5"]));
+        assert!(close(plot["t"], even["t"]) && close(plot["p"], 0.5 * even["p"]), "{:?} {:?}", even, plot);
+        // Weights on the schema's search fields apply when a query names none.
+        let weighted = batman_index(r#"["title^3","plot"]"#);
+        let default = scores(&weighted, serde_json::json!({"type": "text", "query": "batman"}));
+        assert!(close(default["t"], title["t"]) && close(default["p"], title["p"]), "{:?} {:?}", default, title);
+
+        for (field, kind) in [("title^x", ErrorKind::BadRequest), ("title^-1", ErrorKind::BadRequest), ("title^inf", ErrorKind::BadRequest), ("nope^2", ErrorKind::SchemaMismatch)] {
+            let q = serde_json::json!({"query": {"type": "text", "query": "batman", "fields": ["plot", field]}});
+            let e = idx.search(&q.to_string()).unwrap_err();
+            assert_eq!((e.kind, e.path.as_deref()), (kind, Some("query.fields[1]")), "{}", field);
+        }
+    }
+
+    #[test]
+    fn exists_and_missing() {
+        let schema = r#"{"fields":[
+            {"name":"id","type":"text","tokenizer":"raw"},
+            {"name":"year","type":"i64","fast":true},
+            {"name":"rank","type":"i64"},
+            {"name":"rating","type":"f64"},
+            {"name":"note","type":"text"},
+            {"name":"tag","type":"text","tokenizer":"raw","fast":true},
+            {"name":"blob","type":"bytes","indexed":true},
+            {"name":"hidden","type":"text","indexed":false}
+        ]}"#;
+        let docs = [
+            serde_json::json!({"id": "a", "year": 2001, "rank": 3, "rating": 7.5, "note": "good", "tag": "x", "blob": "AAE=", "hidden": "h"}),
+            serde_json::json!({"id": "b"}),
+            serde_json::json!({"id": "c", "year": 1990, "note": "fine"}),
+        ];
+        let idx = TempIndex::with_docs(schema, &docs);
+        let found = |query: serde_json::Value| {
+            let mut found: Vec<String> = ids(&idx.search(&serde_json::json!({ "query": query }).to_string()).unwrap()).into_iter().map(String::from).collect();
+            found.sort();
+            found
+        };
+        // Fast fields read the column; the others scan the term dictionary.
+        for (field, having) in [("year", vec!["a", "c"]), ("tag", vec!["a"]), ("rank", vec!["a"]), ("rating", vec!["a"]), ("note", vec!["a", "c"]), ("blob", vec!["a"])] {
+            assert_eq!(found(serde_json::json!({"type": "exists", "field": field})), having, "{}", field);
+            let missing: Vec<_> = ["a", "b", "c"].into_iter().filter(|id| !having.contains(id)).collect();
+            assert_eq!(found(serde_json::json!({"type": "bool", "must_not": [{"type": "exists", "field": field}]})), missing, "{}", field);
+        }
+
+        for (field, message) in [("nope", "unknown field: nope"), ("hidden", "field is neither fast nor indexed: hidden")] {
+            let q = serde_json::json!({"query": {"type": "exists", "field": field}});
+            let e = idx.search(&q.to_string()).unwrap_err();
+            assert_eq!((e.kind, e.path.as_deref(), e.message.as_str()), (ErrorKind::SchemaMismatch, Some("query.field"), message));
+        }
+    }
+
+    #[test]
+    fn terms_on_text_i64_and_f64() {
+        let schema = r#"{"fields":[
+            {"name":"id","type":"text","tokenizer":"raw"},
+            {"name":"title","type":"text"},
+            {"name":"year","type":"i64"},
+            {"name":"rating","type":"f64"}
+        ]}"#;
+        let docs = [
+            serde_json::json!({"id": "a", "title": "Dark Knight", "year": 2008, "rating": 9.0}),
+            serde_json::json!({"id": "b", "title": "Dark City", "year": 1998, "rating": 7.6}),
+            serde_json::json!({"id": "c", "title": "Heat", "year": -5, "rating": -0.5}),
+        ];
+        let idx = TempIndex::with_docs(schema, &docs);
+        let terms = |field: &str, values: serde_json::Value| {
+            let q = serde_json::json!({"query": {"type": "terms", "field": field, "values": values}});
+            idx.search(&q.to_string()).map(|r| {
+                let mut found: Vec<String> = ids(&r).into_iter().map(String::from).collect();
+                found.sort();
+                found
+            })
+        };
+        // Values are whole terms: exact on a raw field, single tokens on a tokenized one.
+        assert_eq!(terms("id", serde_json::json!(["a", "c", "zz", "a"])).unwrap(), ["a", "c"]);
+        assert_eq!(terms("title", serde_json::json!(["dark"])).unwrap(), ["a", "b"]);
+        assert!(terms("title", serde_json::json!(["Dark Knight", "Heat"])).unwrap().is_empty());
+        assert_eq!(terms("year", serde_json::json!([2008, -5, 0])).unwrap(), ["a", "c"]);
+        assert_eq!(terms("rating", serde_json::json!([7.6, -0.5])).unwrap(), ["b", "c"]);
+        assert_eq!(terms("rating", serde_json::json!([9])).unwrap(), ["a"]);
+        assert!(terms("id", serde_json::json!([])).unwrap().is_empty());
+
+        for (field, values, path) in [
+            ("year", serde_json::json!([2008, 1998.5]), "query.values[1]"),
+            ("year", serde_json::json!(["2008"]), "query.values[0]"),
+            ("rating", serde_json::json!([1.0, "x"]), "query.values[1]"),
+            ("id", serde_json::json!(["a", 1]), "query.values[1]"),
+        ] {
+            let e = terms(field, values).unwrap_err();
+            assert_eq!((e.kind, e.path.as_deref()), (ErrorKind::BadRequest, Some(path)), "{}", field);
+        }
+        let e = terms("nope", serde_json::json!(["a"])).unwrap_err();
+        assert_eq!((e.kind, e.path.as_deref()), (ErrorKind::SchemaMismatch, Some("query.field")));
+    }
+
+    #[test]
+    fn lenient_text_reports_what_it_dropped() {
+        let idx = TempIndex::new(TITLES);
+        for (id, title) in [("a", "batman begins"), ("b", "the joker"), ("c", "batman and joker")] { add(&idx, id, title); }
+        idx.commit().unwrap();
+        idx.refresh().unwrap();
+        let text = |query: &str, lenient: bool| {
+            let q = serde_json::json!({"query": {"type": "text", "query": query, "fields": ["title"], "lenient": lenient}});
+            idx.search(&q.to_string())
+        };
+        for (query, hits, warnings) in [
+            ("batman AND (joker", vec!["c"], vec!["Syntax Error: expected ')' at position 17"]),
+            ("nope:batman joker", vec!["b", "c"], vec!["Field does not exist: 'nope'"]),
+            ("\"batman", vec!["a", "c"], vec!["Syntax Error: missing delimiter \\\" at position 7"]),
+            ("batman joker", vec!["c", "a", "b"], vec![]),
+        ] {
+            let r = text(query, true).unwrap();
+            assert_eq!(ids(&r), hits, "{}", query);
+            assert_eq!(r.warnings, warnings, "{}", query);
+            // Strict parsing fails instead wherever lenient parsing warned.
+            match text(query, false) {
+                Ok(r) => assert!(warnings.is_empty() && r.warnings.is_empty(), "{}", query),
+                Err(e) => assert_eq!((warnings.is_empty(), e.kind, e.path.as_deref()), (false, ErrorKind::BadRequest, Some("query"))),
+            }
+        }
+
+        // Warnings from nested nodes are collected for the whole request, and validate_query reports them too.
+        let q = r#"{"query":{"type":"bool","should":[
+            {"type":"text","query":"batman AND (joker","fields":["title"],"lenient":true},
+            {"type":"text","query":"nope:x","fields":["title"],"lenient":true}
+        ]}}"#;
+        let expected = ["Syntax Error: expected ')' at position 17", "Field does not exist: 'nope'"];
+        assert_eq!(idx.search(q).unwrap().warnings, expected);
+        assert_eq!(idx.validate_query(q).unwrap().warnings, expected);
+    }
+
+    #[test]
+    fn validate_query_normalizes_and_lists_fields() {
+        let idx = TempIndex::new(TITLES);
+        let v = idx.validate_query(r#"{"query":{"type":"bool","must":[{"type":"term_match","field":"id","value":"a"},{"type":"text","query":"x y","fields":["title"]}]},"limit":5}"#).unwrap();
+        assert_eq!(v.fields, ["id", "title"]);
+        assert_eq!((v.request["limit"].as_u64(), v.request["offset"].as_u64()), (Some(5), Some(0)));
+        assert_eq!(v.request["query"]["must"][1]["default_operator"], "or");
+        assert!(v.debug.starts_with("BooleanQuery"), "{}", v.debug);
+        assert!(v.warnings.is_empty());
+        // The flat form normalizes to the envelope.
+        let v = idx.validate_query(r#"{"type":"all"}"#).unwrap();
+        assert_eq!((v.request["query"]["type"].as_str(), v.request["limit"].as_u64()), (Some("all"), Some(100)));
+        assert!(v.fields.is_empty());
+    }
+
+    #[test]
+    fn validate_query_error_paths() {
+        let idx = TempIndex::new(TITLES);
+        for (q, kind, path) in [
+            (r#"{"query":{"type":"bool","must":[{"type":"all"},{"type":"text"}]}}"#, ErrorKind::BadRequest, Some("query.must[1].query")),
+            (r#"{"type":"bool","should":[{"type":"zzz"}]}"#, ErrorKind::BadRequest, Some("should[0]")),
+            (r#"{"query":{"type":"dis_max","queries":[{"type":"term_match","field":"nope","value":"a"}]}}"#, ErrorKind::SchemaMismatch, Some("query.queries[0].field")),
+            (r#"{"type":"dis_max","queries":[{"type":"term_match","field":"nope","value":"a"}]}"#, ErrorKind::SchemaMismatch, Some("queries[0].field")),
+            (r#"{"query":{"type":"constant_score","query":{"type":"bool","must":[{"type":"regex","pattern":"a{99}","fields":["id"]}]}}}"#, ErrorKind::BadRequest, Some("query.query.must[0].pattern")),
+            (r#"{"query":{"type":"all"},"scoring":"tfidf"}"#, ErrorKind::BadRequest, Some("scoring")),
+            (r#"{"query":{"type":"all"},"count_only":true,"explain":true}"#, ErrorKind::BadRequest, Some("count_only")),
+            ("[1]", ErrorKind::BadRequest, None),
+            ("", ErrorKind::BadRequest, None),
+        ] {
+            let e = idx.validate_query(q).unwrap_err();
+            assert_eq!((e.kind, e.path.as_deref()), (kind, path), "{}", q);
+            // Searching fails the same way.
+            assert_eq!(idx.search(q).unwrap_err().path, e.path, "{}", q);
+        }
+    }
+
+    #[test]
+    fn explain_attaches_score_trees() {
+        let schema = r#"{"fields":[{"name":"id","type":"text","tokenizer":"raw"},{"name":"title","type":"text"},{"name":"year","type":"i64","fast":true}]}"#;
+        let docs = [
+            serde_json::json!({"id": "a", "title": "batman begins", "year": 2005}),
+            serde_json::json!({"id": "b", "title": "the joker", "year": 2019}),
+        ];
+        let idx = TempIndex::with_docs(schema, &docs);
+        let explained = |q: &str| -> Vec<(Option<Score>, serde_json::Value)> {
+            idx.search(q).unwrap().results.into_iter().map(|mut h| {
+                let score = match h.get("_score") { Some(HitValue::F32(s)) => Some(*s), _ => None };
+                match h.remove("_explanation") { Some(HitValue::Json(e)) => (score, e), v => panic!("{:?}", v) }
+            }).collect()
+        };
+
+        // The root value is the hit's score; BM25 parts and boosts are nested under it.
+        let hits = explained(r#"{"query":{"type":"text","query":"batman","fields":["title"],"boost":2},"explain":true}"#);
+        let [(Some(score), e)] = &hits[..] else { panic!("{:?}", hits) };
+        assert_eq!(e["value"].as_f64().map(|v| v as Score), Some(*score));
+        assert_eq!(e["description"], "Boost x2 of ...");
+        let term = &e["details"][0];
+        assert_eq!(term["description"], "TermQuery, product of...");
+        assert_eq!(term["context"][0], r#"Term=Term(field=1, type=Str, "batman")"#);
+        assert_eq!((term["value"].as_f64().unwrap() * 2.0) as Score, *score);
+
+        // function_score wraps the query's explanation with each function's value.
+        let hits = explained(r#"{"query":{"type":"function_score","query":{"type":"all"},"functions":[{"field_value_factor":{"field":"year","factor":0.001}}]},"explain":true}"#);
+        assert_eq!(hits.len(), 2);
+        for (score, e) in &hits {
+            assert_eq!(e["value"].as_f64().map(|v| v as Score), *score);
+            assert_eq!(e["description"], "function_score, score_mode=multiply, boost_mode=multiply");
+            assert_eq!(e["details"][0]["description"], "AllQuery");
+            assert_eq!(e["details"][1]["description"], "field_value_factor(year), weight=1");
+        }
+
+        // Sorted hits have no _score, but still explain the match.
+        for q in [r#"{"query":{"type":"all"},"sort":{"field":"year"},"explain":true}"#, r#"{"query":{"type":"all"},"scoring":"none","explain":true}"#] {
+            let hits = explained(q);
+            assert_eq!(hits.len(), 2);
+            assert!(hits.iter().all(|(_, e)| e["description"] == "AllQuery"), "{:?}", hits);
+        }
+        assert!(explained(r#"{"query":{"type":"all"},"sort":{"field":"year"},"explain":true}"#).iter().all(|(score, _)| score.is_none()));
+        let r = idx.search(r#"{"query":{"type":"all"}}"#).unwrap();
+        assert!(r.results.iter().all(|h| !h.contains_key("_explanation")));
+    }
+
+    /// Values of `v` by id; `f` has none.
+    fn valued_index() -> TempIndex {
+        let schema = r#"{"fields":[{"name":"id","type":"text","tokenizer":"raw"},{"name":"v","type":"f64","fast":true},{"name":"title","type":"text"}]}"#;
+        let mut docs: Vec<_> = [("a", 0.0), ("b", 1.0), ("c", 9.0), ("d", 99.0), ("e", -4.0), ("g", 10.0), ("h", 20.0)].iter()
+            .map(|(id, v)| serde_json::json!({ "id": id, "v": v })).collect();
+        docs.push(serde_json::json!({"id": "f", "title": "t"}));
+        TempIndex::with_docs(schema, &docs)
+    }
+
+    /// Scores of a function_score over every document, each scoring 2 before the functions.
+    fn function_scores(idx: &TantivyIndex, functions: serde_json::Value, score_mode: &str, boost_mode: &str) -> BTreeMap<String, Score> {
+        scores(idx, serde_json::json!({
+            "type": "function_score",
+            "query": {"type": "constant_score", "query": {"type": "all"}, "score": 2},
+            "functions": functions, "score_mode": score_mode, "boost_mode": boost_mode,
+        }))
+    }
+
+    fn assert_scores(got: &BTreeMap<String, Score>, want: &[(&str, f64)], what: &str) {
+        for (id, want) in want {
+            assert!(close(got[*id], *want as Score), "{} {}: got {}, want {}", what, id, got[*id], want);
+        }
+    }
+
+    #[test]
+    fn function_score_modifiers() {
+        let idx = valued_index();
+        for (modifier, want) in [
+            ("none", vec![("a", 0.0), ("c", 9.0), ("e", -4.0)]),
+            ("log", vec![("a", 0.0), ("d", 99f64.log10()), ("e", 0.0)]),
+            ("log1p", vec![("a", 0.0), ("d", 2.0), ("e", 0.0)]),
+            ("log2p", vec![("a", 2f64.log10()), ("d", 101f64.log10())]),
+            ("ln", vec![("a", 0.0), ("b", 0.0), ("g", 10f64.ln())]),
+            ("ln1p", vec![("b", 2f64.ln()), ("d", 100f64.ln()), ("e", 0.0)]),
+            ("ln2p", vec![("a", 2f64.ln()), ("e", 0.0)]),
+            ("square", vec![("c", 81.0), ("e", 16.0)]),
+            ("sqrt", vec![("c", 3.0), ("e", 0.0)]),
+            ("reciprocal", vec![("a", 0.0), ("e", -0.25), ("h", 0.05)]),
+        ] {
+            let functions = serde_json::json!([{"field_value_factor": {"field": "v", "modifier": modifier}}]);
+            let got = function_scores(&idx, functions, "multiply", "replace");
+            assert_scores(&got, &want, modifier);
+            // No value and no `missing`: the query score stands.
+            assert_eq!(got["f"], 2.0, "{}", modifier);
+        }
+        // factor, weight and missing.
+        let functions = serde_json::json!([{"field_value_factor": {"field": "v", "factor": 0.5, "modifier": "square", "missing": 6}, "weight": 3}]);
+        assert_scores(&function_scores(&idx, functions, "multiply", "replace"), &[("c", 3.0 * 4.5 * 4.5), ("f", 3.0 * 9.0)], "factor");
+    }
+
+    #[test]
+    fn function_score_decay() {
+        let idx = valued_index();
+        let decay = |kind: &str, offset: f64| {
+            let functions = serde_json::json!([{ kind: {"field": "v", "origin": 0, "scale": 10, "offset": offset} }]);
+            function_scores(&idx, functions, "multiply", "replace")
+        };
+        // At k scales past the offset: gauss decay^(k^2), exp decay^k, linear 1 - k(1 - decay).
+        assert_scores(&decay("gauss", 0.0), &[("a", 1.0), ("g", 0.5), ("h", 0.0625)], "gauss");
+        assert_scores(&decay("exp", 0.0), &[("a", 1.0), ("g", 0.5), ("h", 0.25)], "exp");
+        assert_scores(&decay("linear", 0.0), &[("a", 1.0), ("g", 0.5), ("h", 0.0), ("d", 0.0)], "linear");
+        // Distance is measured both ways and starts past the offset.
+        assert_scores(&decay("exp", 4.0), &[("b", 1.0), ("e", 1.0), ("h", 0.5f64.powf(1.6))], "exp offset");
+        assert_scores(&decay("linear", 10.0), &[("g", 1.0), ("h", 0.5)], "linear offset");
+        let functions = serde_json::json!([{"gauss": {"field": "v", "origin": 0, "scale": 10, "decay": 0.25}}]);
+        assert_scores(&function_scores(&idx, functions, "multiply", "replace"), &[("g", 0.25), ("h", 0.25f64.powi(4))], "gauss decay");
+    }
+
+    #[test]
+    fn function_score_modes() {
+        let idx = valued_index();
+        // For g (v = 10): the factor gives 10 and the linear decay 0.5.
+        let functions = serde_json::json!([
+            {"field_value_factor": {"field": "v"}},
+            {"linear": {"field": "v", "origin": 0, "scale": 10}},
+        ]);
+        for (score_mode, combined) in [("multiply", 5.0), ("sum", 10.5), ("avg", 5.25), ("max", 10.0), ("min", 0.5)] {
+            for (boost_mode, score) in [("multiply", 2.0 * combined), ("sum", 2.0 + combined), ("replace", combined)] {
+                let got = function_scores(&idx, functions.clone(), score_mode, boost_mode);
+                assert_scores(&got, &[("g", score), ("f", 2.0)], &format!("{} {}", score_mode, boost_mode));
+            }
+        }
+
+        for (functions, score_mode, path) in [
+            (serde_json::json!([{"field_value_factor": {"field": "v", "modifier": "cube"}}]), "sum", "query.functions[0].field_value_factor.modifier"),
+            (serde_json::json!([{"field_value_factor": {"field": "title"}}]), "sum", "query.functions[0].field_value_factor.field"),
+            (serde_json::json!([{"exp": {"field": "v", "origin": 0, "scale": 1, "decay": 1}}]), "sum", "query.functions[0].exp.decay"),
+            (serde_json::json!([{"gauss": {"field": "v", "origin": 0, "scale": 0}}]), "sum", "query.functions[0].gauss.scale"),
+            (serde_json::json!([{"linear": {"field": "v", "origin": 0, "scale": 1, "offset": -1}}]), "sum", "query.functions[0].linear.offset"),
+            (serde_json::json!([{"field_value_factor": {"field": "v"}}, {"field_value_factor": {"field": "v"}, "weight": -1}]), "sum", "query.functions[1].weight"),
+            (serde_json::json!([{"field_value_factor": {"field": "v"}}]), "median", "query.score_mode"),
+        ] {
+            let q = serde_json::json!({"query": {"type": "function_score", "query": {"type": "all"}, "functions": functions, "score_mode": score_mode}});
+            let e = idx.search(&q.to_string()).unwrap_err();
+            assert_eq!(e.path.as_deref(), Some(path), "{}", e.message);
+        }
+    }
+
+    #[test]
+    fn min_score_and_count_only() {
+        let idx = valued_index();
+        // Scores are the value of `v`; `f`, without one, keeps the query's 2.
+        let search = |options: &str| {
+            let q = format!(
+                r#"{{"query":{{"type":"function_score","query":{{"type":"constant_score","query":{{"type":"all"}},"score":2}},"functions":[{{"field_value_factor":{{"field":"v"}}}}],"boost_mode":"replace"}}{}}}"#,
+                options,
+            );
+            idx.search(&q)
+        };
+        let r = search(r#","min_score":5"#).unwrap();
+        assert_eq!((ids(&r), r.count, r.total_count), (vec!["d", "h", "g", "c"], 4, 4));
+        // The bound is inclusive and applies before paging.
+        let r = search(r#","min_score":10,"limit":1,"offset":1"#).unwrap();
+        assert_eq!((ids(&r), r.count, r.total_count), (vec!["h"], 1, 3));
+        let r = search(r#","min_score":0.5,"count_only":true"#).unwrap();
+        assert_eq!((r.results.len(), r.count, r.total_count), (0, 0, 6));
+        assert_eq!(search(r#","count_only":true"#).unwrap().total_count, 8);
+
+        // On a plain query, and in the flat form.
+        let r = idx.search(r#"{"query":{"type":"constant_score","query":{"type":"all"},"score":2},"min_score":2,"sort":{"field":"v"}}"#).unwrap();
+        assert_eq!(r.total_count, 8);
+        let r = idx.search(r#"{"type":"constant_score","query":{"type":"all"},"score":2,"min_score":2.5,"count_only":true}"#).unwrap();
+        assert_eq!(r.total_count, 0);
+
+        for (options, path) in [
+            (r#","min_score":1,"scoring":"none""#, "min_score"),
+            (r#","count_only":true,"explain":true"#, "count_only"),
+            (r#","count_only":true,"highlight":{"fields":["title"]}"#, "count_only"),
+            (r#","count_only":true,"collapse":{"field":"v"}"#, "count_only"),
+        ] {
+            let e = search(options).unwrap_err();
+            assert_eq!((e.kind, e.path.as_deref()), (ErrorKind::BadRequest, Some(path)), "{}", options);
+        }
+    }
+
+    const TITLES: &str = r#"{"fields":[{"name":"id","type":"text","tokenizer":"raw"},{"name":"title","type":"text"}]}"#;
+
+    fn add(idx: &TantivyIndex, id: &str, title: &str) -> Opstamp {
+        idx.add_doc(&serde_json::json!({ "id": id, "title": title }).to_string()).unwrap()
+    }
+
+    #[test]
+    fn write_opstamps_increase_and_commit_covers_them() {
+        let idx = TempIndex::new(TITLES);
+        let a = add(&idx, "a", "first");
+        let b = add(&idx, "b", "second");
+        let d = idx.delete_term("id", &serde_json::json!("a")).unwrap();
+        assert!(a < b && b < d, "{} {} {}", a, b, d);
+        assert!(!idx.wait_for_opstamp(d, 0).unwrap());
+        let c = idx.commit().unwrap();
+        assert!(c > d);
+        assert!(idx.wait_for_opstamp(d, 0).unwrap());
+        assert_eq!(idx.num_docs(), 1);
+    }
+
+    #[test]
+    fn wait_for_opstamp_times_out() {
+        let idx = TempIndex::new(TITLES);
+        let op = add(&idx, "a", "first");
+        let start = Instant::now();
+        assert!(!idx.wait_for_opstamp(op, 50).unwrap());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn reopen_seeds_the_watermark() {
+        let idx = TempIndex::new(TITLES);
+        let op = add(&idx, "a", "first");
+        let c = idx.commit().unwrap();
+        let idx = idx.reopen("");
+        // Operations committed before the reopen are visible right away...
+        assert!(idx.wait_for_opstamp(op, 0).unwrap());
+        // ...and new ones continue from the stored opstamp.
+        let next = add(&idx, "b", "second");
+        assert!(next >= c);
+        assert!(!idx.wait_for_opstamp(next, 0).unwrap());
+    }
+
+    #[test]
+    fn close_wakes_waiters() {
+        let idx = Arc::new(TempIndex::new(TITLES));
+        let op = add(&idx, "a", "first");
+        let waiter = {
+            let idx = idx.clone();
+            std::thread::spawn(move || idx.wait_for_opstamp(op, 60_000))
+        };
+        std::thread::sleep(Duration::from_millis(20));
+        let start = Instant::now();
+        idx.close().unwrap();
+        let e = waiter.join().unwrap().unwrap_err();
+        assert_eq!((e.kind, e.message.as_str()), (ErrorKind::NotFound, "index closed"));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn auto_commit_after_max_docs() {
+        let idx = TempIndex::with_options(TITLES, r#"{"auto_commit":{"max_docs":3}}"#);
+        add(&idx, "a", "first");
+        let op = add(&idx, "b", "second");
+        // Below the threshold nothing is committed.
+        assert!(!idx.wait_for_opstamp(op, 100).unwrap());
+        let op = add(&idx, "c", "third");
+        assert!(idx.wait_for_opstamp(op, 5_000).unwrap());
+        assert_eq!(idx.num_docs(), 3);
+
+        // Turning auto-commit off also turns off the doc-count trigger.
+        idx.set_auto_commit(r#"{"max_docs":0}"#).unwrap();
+        let op = (0..4).map(|i| add(&idx, &format!("d{}", i), "more")).last().unwrap();
+        assert!(!idx.wait_for_opstamp(op, 100).unwrap());
+    }
+
+    #[test]
+    fn auto_commit_on_interval() {
+        let idx = TempIndex::new(TITLES);
+        idx.set_auto_commit(r#"{"interval_ms":20}"#).unwrap();
+        let op = add(&idx, "a", "first");
+        assert!(idx.wait_for_opstamp(op, 5_000).unwrap());
+        assert_eq!(idx.num_docs(), 1);
+        assert!(idx.last_error().is_none());
+    }
+
+    #[test]
+    fn failed_auto_commit_sets_last_error() {
+        let idx = TempIndex::new(TITLES);
+        idx.set_auto_commit(r#"{"interval_ms":10}"#).unwrap();
+        // With the directory gone, the background commit can't write its segment.
+        std::fs::remove_dir_all(idx.path()).unwrap();
+        add(&idx, "a", "first");
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while idx.last_error().is_none() && Instant::now() < deadline { std::thread::sleep(Duration::from_millis(10)); }
+        let e = idx.last_error().expect("no background commit error");
+        assert_eq!(e.kind, ErrorKind::Io);
+    }
+
+    #[test]
+    fn manual_reload_waits_for_refresh() {
+        let idx = TempIndex::with_options(TITLES, r#"{"reload_policy":"manual"}"#);
+        let op = add(&idx, "a", "first");
+        idx.commit().unwrap();
+        // Committed, but neither searches nor the watermark see it yet.
+        assert_eq!(idx.num_docs(), 0);
+        assert_eq!(idx.search(r#"{"type":"all"}"#).unwrap().total_count, 0);
+        assert!(!idx.wait_for_opstamp(op, 50).unwrap());
+
+        idx.refresh().unwrap();
+        assert_eq!(idx.num_docs(), 1);
+        assert!(idx.wait_for_opstamp(op, 0).unwrap());
+
+        // Refresh only publishes commits, not pending writes.
+        let op = add(&idx, "b", "second");
+        idx.refresh().unwrap();
+        assert_eq!(idx.num_docs(), 1);
+        assert!(!idx.wait_for_opstamp(op, 0).unwrap());
+    }
+
+    #[test]
+    fn unknown_reload_policy() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let e = TantivyIndex::create_with_options(dir.path().to_str().unwrap(), TITLES, r#"{"reload_policy":"never"}"#).err().unwrap();
+        assert_eq!((e.kind, e.path.as_deref()), (ErrorKind::BadRequest, Some("reload_policy")));
+    }
+
+    #[test]
+    fn i64_range_bounds_are_inclusive() {
+        let schema = r#"{"fields":[{"name":"id","type":"text","tokenizer":"raw"},{"name":"n","type":"i64","fast":true}]}"#;
+        let values = [("min", i64::MIN), ("one", 1), ("two", 2), ("three", 3), ("max", i64::MAX)];
+        let docs: Vec<_> = values.iter().map(|(id, n)| serde_json::json!({ "id": id, "n": n })).collect();
+        let idx = TempIndex::with_docs(schema, &docs);
+        let range = |bounds: serde_json::Value| {
+            let mut q = serde_json::json!({"type":"range_i64","field":"n","limit":10});
+            q.as_object_mut().unwrap().extend(bounds.as_object().unwrap().clone());
+            let mut found: Vec<String> = ids(&idx.search(&q.to_string()).unwrap()).into_iter().map(String::from).collect();
+            found.sort();
+            found
+        };
+        assert_eq!(range(serde_json::json!({"min": 1, "max": 2})), ["one", "two"]);
+        // `max + 1` used to overflow here.
+        assert_eq!(range(serde_json::json!({"min": 3, "max": i64::MAX})), ["max", "three"]);
+        assert_eq!(range(serde_json::json!({"max": i64::MIN})), ["min"]);
+        assert_eq!(range(serde_json::json!({})).len(), 5);
+    }
+
+    #[test]
+    fn nested_request_options_warn() {
+        let req = SearchRequest::parse(r#"{"type":"bool","limit":5,"must":[{"type":"all","limit":10}]}"#).unwrap();
+        assert_eq!(req.limit, 5);
+        assert_eq!(req.warnings.len(), 1);
+        assert!(req.warnings[0].starts_with("must[0].limit:"));
+
+        let req = SearchRequest::parse(r#"{"query":{"type":"all","offset":3},"limit":2}"#).unwrap();
+        assert_eq!((req.limit, req.offset), (2, 0));
+        assert_eq!(req.warnings.len(), 1);
+        assert!(req.warnings[0].starts_with("query.offset:"));
     }
-    o
 }
@@ -0,0 +1,562 @@
+//! Query types tantivy does not provide out of the box.
+
+use tantivy::fieldnorm::FieldNormReader;
+use tantivy::postings::{Postings, SegmentPostings};
+use tantivy::query::{
+    BooleanQuery, ConstScoreQuery, EmptyScorer, EnableScoring, Explanation, MoreLikeThisQuery, Occur, Query, Scorer,
+    TermQuery, Weight,
+};
+use tantivy::schema::IndexRecordOption;
+use tantivy::{DocId, DocSet, Score, SegmentReader, TantivyError, Term, TERMINATED};
+
+/// Matches documents that match at least `min` of `clauses`. The score is the
+/// sum of the matching clauses' scores, like a `should` group in a `BooleanQuery`.
+#[derive(Debug)]
+pub(crate) struct MinShouldMatchQuery {
+    clauses: Vec<Box<dyn Query>>,
+    min: usize,
+}
+
+impl Clone for MinShouldMatchQuery {
+    fn clone(&self) -> Self {
+        MinShouldMatchQuery { clauses: self.clauses.iter().map(|q| q.box_clone()).collect(), min: self.min }
+    }
+}
+
+impl MinShouldMatchQuery {
+    pub(crate) fn new(clauses: Vec<Box<dyn Query>>, min: usize) -> Self {
+        MinShouldMatchQuery { clauses, min }
+    }
+}
+
+impl Query for MinShouldMatchQuery {
+    fn weight(&self, enable_scoring: EnableScoring<'_>) -> tantivy::Result<Box<dyn Weight>> {
+        let weights = self.clauses.iter()
+            .map(|q| q.weight(enable_scoring))
+            .collect::<tantivy::Result<Vec<_>>>()?;
+        Ok(Box::new(MinShouldMatchWeight { weights, min: self.min }))
+    }
+
+    fn query_terms<'a>(&'a self, visitor: &mut dyn FnMut(&'a Term, bool)) {
+        for q in &self.clauses { q.query_terms(visitor); }
+    }
+}
+
+struct MinShouldMatchWeight {
+    weights: Vec<Box<dyn Weight>>,
+    min: usize,
+}
+
+impl Weight for MinShouldMatchWeight {
+    fn scorer(&self, reader: &SegmentReader, boost: Score) -> tantivy::Result<Box<dyn Scorer>> {
+        let scorers = self.weights.iter()
+            .map(|w| w.scorer(reader, boost))
+            .collect::<tantivy::Result<Vec<_>>>()?;
+        Ok(Box::new(MinShouldMatchScorer::new(scorers, self.min)))
+    }
+
+    fn explain(&self, reader: &SegmentReader, doc: DocId) -> tantivy::Result<Explanation> {
+        let mut scorer = self.scorer(reader, 1.0)?;
+        if scorer.seek(doc) != doc {
+            return Err(tantivy::TantivyError::InvalidArgument(format!("Document #({doc}) does not match")));
+        }
+        let mut expl = Explanation::new_with_string(format!("MinShouldMatch(min={})", self.min), scorer.score());
+        for w in &self.weights {
+            if let Ok(e) = w.explain(reader, doc) { expl.add_detail(e); }
+        }
+        Ok(expl)
+    }
+}
+
+struct MinShouldMatchScorer {
+    scorers: Vec<Box<dyn Scorer>>,
+    min: usize,
+    doc: DocId,
+    score: Score,
+}
+
+impl MinShouldMatchScorer {
+    fn new(scorers: Vec<Box<dyn Scorer>>, min: usize) -> Self {
+        let mut s = MinShouldMatchScorer { scorers, min: min.max(1), doc: 0, score: 0.0 };
+        s.find_match();
+        s
+    }
+
+    /// Move to the lowest doc at or after the scorers' current positions that
+    /// enough clauses agree on.
+    fn find_match(&mut self) -> DocId {
+        loop {
+            let doc = self.scorers.iter().map(|s| s.doc()).min().unwrap_or(TERMINATED);
+            if doc == TERMINATED {
+                self.doc = TERMINATED;
+                return TERMINATED;
+            }
+            let matching = self.scorers.iter().filter(|s| s.doc() == doc).count();
+            if matching >= self.min {
+                self.doc = doc;
+                self.score = self.scorers.iter_mut().filter(|s| s.doc() == doc).map(|s| s.score()).sum();
+                return doc;
+            }
+            for s in self.scorers.iter_mut().filter(|s| s.doc() == doc) { s.advance(); }
+        }
+    }
+}
+
+impl DocSet for MinShouldMatchScorer {
+    fn advance(&mut self) -> DocId {
+        let doc = self.doc;
+        for s in self.scorers.iter_mut().filter(|s| s.doc() == doc) { s.advance(); }
+        self.find_match()
+    }
+
+    fn seek(&mut self, target: DocId) -> DocId {
+        if self.doc >= target { return self.doc; }
+        for s in self.scorers.iter_mut().filter(|s| s.doc() < target) { s.seek(target); }
+        self.find_match()
+    }
+
+    fn doc(&self) -> DocId { self.doc }
+
+    fn size_hint(&self) -> u32 {
+        self.scorers.iter().map(|s| s.size_hint()).max().unwrap_or(0)
+    }
+}
+
+impl Scorer for MinShouldMatchScorer {
+    fn score(&mut self) -> Score { self.score }
+}
+
+/// Matches documents containing every term, with at most `distance` other
+/// words between the first and last of them; if `ordered`, in the given
+/// order. Scored like a conjunction of the terms, under BM25 `(k1, b)` if set.
+#[derive(Clone, Debug)]
+pub(crate) struct ProximityQuery {
+    terms: Vec<Term>,
+    distance: u32,
+    ordered: bool,
+    bm25: Option<(Score, Score)>,
+}
+
+impl ProximityQuery {
+    /// `terms` must all belong to the same field.
+    pub(crate) fn new(mut terms: Vec<Term>, distance: u32, ordered: bool, bm25: Option<(Score, Score)>) -> Self {
+        if !ordered {
+            terms.sort();
+            terms.dedup();
+        }
+        ProximityQuery { terms, distance, ordered, bm25 }
+    }
+}
+
+impl Query for ProximityQuery {
+    fn weight(&self, enable_scoring: EnableScoring<'_>) -> tantivy::Result<Box<dyn Weight>> {
+        let mut distinct = self.terms.clone();
+        distinct.sort();
+        distinct.dedup();
+        let clauses = distinct.into_iter()
+            .map(|t| {
+                let q: Box<dyn Query> = match self.bm25 {
+                    Some((k1, b)) => Box::new(Bm25TermQuery::new(t, IndexRecordOption::WithFreqs, k1, b)),
+                    None => Box::new(TermQuery::new(t, IndexRecordOption::WithFreqs)),
+                };
+                (Occur::Must, q)
+            })
+            .collect();
+        let inner = BooleanQuery::new(clauses).weight(enable_scoring)?;
+        Ok(Box::new(ProximityWeight { inner, terms: self.terms.clone(), distance: self.distance, ordered: self.ordered }))
+    }
+
+    fn query_terms<'a>(&'a self, visitor: &mut dyn FnMut(&'a Term, bool)) {
+        for t in &self.terms { visitor(t, true); }
+    }
+}
+
+struct ProximityWeight {
+    inner: Box<dyn Weight>,
+    terms: Vec<Term>,
+    distance: u32,
+    ordered: bool,
+}
+
+impl Weight for ProximityWeight {
+    fn scorer(&self, reader: &SegmentReader, boost: Score) -> tantivy::Result<Box<dyn Scorer>> {
+        let Some(first) = self.terms.first() else { return Ok(Box::new(EmptyScorer)) };
+        let inverted = reader.inverted_index(first.field())?;
+        let mut postings = Vec::with_capacity(self.terms.len());
+        for t in &self.terms {
+            match inverted.read_postings(t, IndexRecordOption::WithFreqsAndPositions)? {
+                Some(p) => postings.push(p),
+                None => return Ok(Box::new(EmptyScorer)),
+            }
+        }
+        let inner = self.inner.scorer(reader, boost)?;
+        Ok(Box::new(ProximityScorer::new(inner, postings, self.distance, self.ordered)))
+    }
+
+    fn explain(&self, reader: &SegmentReader, doc: DocId) -> tantivy::Result<Explanation> {
+        let mut scorer = self.scorer(reader, 1.0)?;
+        if scorer.seek(doc) != doc {
+            return Err(tantivy::TantivyError::InvalidArgument(format!("Document #({doc}) does not match")));
+        }
+        let mut expl = Explanation::new_with_string(format!("Proximity(distance={}, ordered={})", self.distance, self.ordered), scorer.score());
+        expl.add_detail(self.inner.explain(reader, doc)?);
+        Ok(expl)
+    }
+}
+
+struct ProximityScorer {
+    inner: Box<dyn Scorer>,
+    postings: Vec<SegmentPostings>,
+    positions: Vec<Vec<u32>>,
+    distance: u32,
+    ordered: bool,
+}
+
+impl ProximityScorer {
+    fn new(inner: Box<dyn Scorer>, postings: Vec<SegmentPostings>, distance: u32, ordered: bool) -> Self {
+        let positions = vec![Vec::new(); postings.len()];
+        let mut s = ProximityScorer { inner, postings, positions, distance, ordered };
+        if s.inner.doc() != TERMINATED && !s.within_distance() { s.advance(); }
+        s
+    }
+
+    /// Whether the current doc has a window holding every term.
+    fn within_distance(&mut self) -> bool {
+        let doc = self.inner.doc();
+        for (p, buf) in self.postings.iter_mut().zip(self.positions.iter_mut()) {
+            if p.seek(doc) != doc { return false; }
+            p.positions(buf);
+        }
+        let slack = self.distance as u64 + self.positions.len() as u64 - 1;
+        if self.ordered { return self.within_distance_ordered(slack); }
+        // Smallest window covering one position of each term: repeatedly
+        // advance the list holding the lowest position.
+        let mut idx = vec![0usize; self.positions.len()];
+        loop {
+            let mut lo = (u32::MAX, 0);
+            let mut hi = 0;
+            for (i, list) in self.positions.iter().enumerate() {
+                let pos = list[idx[i]];
+                if pos < lo.0 { lo = (pos, i); }
+                hi = hi.max(pos);
+            }
+            if (hi - lo.0) as u64 <= slack { return true; }
+            idx[lo.1] += 1;
+            if idx[lo.1] == self.positions[lo.1].len() { return false; }
+        }
+    }
+
+    /// Whether some window of at most `slack` holds the terms at strictly
+    /// increasing positions. From each start, greedily taking the next
+    /// position of each following term gives the tightest window; cursors
+    /// only move forward as the start does.
+    fn within_distance_ordered(&self, slack: u64) -> bool {
+        let mut idx = vec![0usize; self.positions.len()];
+        for &start in &self.positions[0] {
+            let mut prev = start;
+            for i in 1..self.positions.len() {
+                let list = &self.positions[i];
+                while idx[i] < list.len() && list[idx[i]] <= prev { idx[i] += 1; }
+                let Some(&pos) = list.get(idx[i]) else { return false };
+                prev = pos;
+            }
+            if (prev - start) as u64 <= slack { return true; }
+        }
+        false
+    }
+}
+
+impl DocSet for ProximityScorer {
+    fn advance(&mut self) -> DocId {
+        loop {
+            let doc = self.inner.advance();
+            if doc == TERMINATED || self.within_distance() { return doc; }
+        }
+    }
+
+    fn seek(&mut self, target: DocId) -> DocId {
+        let doc = self.inner.seek(target);
+        if doc == TERMINATED || self.within_distance() { return doc; }
+        self.advance()
+    }
+
+    fn doc(&self) -> DocId { self.inner.doc() }
+
+    fn size_hint(&self) -> u32 { self.inner.size_hint() }
+}
+
+impl Scorer for ProximityScorer {
+    fn score(&mut self) -> Score { self.inner.score() }
+}
+
+/// A term scored with BM25 under its own `k1` and `b`; tantivy's `TermQuery`
+/// hardcodes 1.2 and 0.75.
+#[derive(Clone, Debug)]
+pub(crate) struct Bm25TermQuery {
+    term: Term,
+    option: IndexRecordOption,
+    k1: Score,
+    b: Score,
+}
+
+impl Bm25TermQuery {
+    pub(crate) fn new(term: Term, option: IndexRecordOption, k1: Score, b: Score) -> Self {
+        Bm25TermQuery { term, option, k1, b }
+    }
+}
+
+impl Query for Bm25TermQuery {
+    fn weight(&self, enable_scoring: EnableScoring<'_>) -> tantivy::Result<Box<dyn Weight>> {
+        let EnableScoring::Enabled { statistics_provider: stats, .. } = enable_scoring else {
+            return TermQuery::new(self.term.clone(), self.option).weight(enable_scoring);
+        };
+        let num_docs = stats.total_num_docs()?;
+        let doc_freq = stats.doc_freq(&self.term)?;
+        let avgdl = stats.total_num_tokens(self.term.field())? as Score / num_docs.max(1) as Score;
+        let idf = (1.0 + (num_docs.saturating_sub(doc_freq) as Score + 0.5) / (doc_freq as Score + 0.5)).ln();
+        let mut norms = [0.0; 256];
+        for (id, n) in norms.iter_mut().enumerate() {
+            let dl = FieldNormReader::id_to_fieldnorm(id as u8) as Score;
+            *n = self.k1 * (1.0 - self.b + self.b * dl / avgdl);
+        }
+        Ok(Box::new(Bm25TermWeight { query: self.clone(), idf, avgdl, norms }))
+    }
+
+    fn query_terms<'a>(&'a self, visitor: &mut dyn FnMut(&'a Term, bool)) {
+        visitor(&self.term, false);
+    }
+}
+
+struct Bm25TermWeight {
+    query: Bm25TermQuery,
+    idf: Score,
+    avgdl: Score,
+    // k1 * (1 - b + b * dl / avgdl) for each fieldnorm id.
+    norms: [Score; 256],
+}
+
+impl Weight for Bm25TermWeight {
+    fn scorer(&self, reader: &SegmentReader, boost: Score) -> tantivy::Result<Box<dyn Scorer>> {
+        let field = self.query.term.field();
+        let Some(postings) = reader.inverted_index(field)?.read_postings(&self.query.term, self.query.option)? else {
+            return Ok(Box::new(EmptyScorer));
+        };
+        let fieldnorms = reader.fieldnorms_readers().get_field(field)?
+            .unwrap_or_else(|| FieldNormReader::constant(reader.max_doc(), 1));
+        let weight = boost * self.idf * (self.query.k1 + 1.0);
+        Ok(Box::new(Bm25TermScorer { postings, fieldnorms, weight, norms: self.norms }))
+    }
+
+    fn explain(&self, reader: &SegmentReader, doc: DocId) -> tantivy::Result<Explanation> {
+        let mut scorer = self.scorer(reader, 1.0)?;
+        if scorer.seek(doc) != doc {
+            return Err(tantivy::TantivyError::InvalidArgument(format!("Document #({doc}) does not match")));
+        }
+        let fieldnorms = reader.fieldnorms_readers().get_field(self.query.term.field())?;
+        let dl = fieldnorms.map(|r| r.fieldnorm(doc)).unwrap_or(1) as Score;
+        let mut postings = reader.inverted_index(self.query.term.field())?
+            .read_postings(&self.query.term, self.query.option)?
+            .ok_or_else(|| tantivy::TantivyError::InvalidArgument("term vanished".to_string()))?;
+        postings.seek(doc);
+        let tf = postings.term_freq() as Score;
+        let (k1, b) = (self.query.k1, self.query.b);
+        let mut expl = Explanation::new_with_string(format!("TermQuery-BM25(k1={k1}, b={b}), product of..."), scorer.score());
+        expl.add_const("(k1+1)", k1 + 1.0);
+        expl.add_const("idf, computed as log(1 + (N - n + 0.5) / (n + 0.5))", self.idf);
+        let mut tf_expl = Explanation::new("freq / (freq + k1 * (1 - b + b * dl / avgdl))", tf / (tf + k1 * (1.0 - b + b * dl / self.avgdl)));
+        tf_expl.add_const("freq, occurrences of term within document", tf);
+        tf_expl.add_const("dl, length of field", dl);
+        tf_expl.add_const("avgdl, average length of field", self.avgdl);
+        expl.add_detail(tf_expl);
+        Ok(expl)
+    }
+}
+
+struct Bm25TermScorer {
+    postings: SegmentPostings,
+    fieldnorms: FieldNormReader,
+    weight: Score,
+    norms: [Score; 256],
+}
+
+impl DocSet for Bm25TermScorer {
+    fn advance(&mut self) -> DocId { self.postings.advance() }
+
+    fn seek(&mut self, target: DocId) -> DocId { self.postings.seek(target) }
+
+    fn doc(&self) -> DocId { self.postings.doc() }
+
+    fn size_hint(&self) -> u32 { self.postings.size_hint() }
+}
+
+impl Scorer for Bm25TermScorer {
+    fn score(&mut self) -> Score {
+        let tf = self.postings.term_freq() as Score;
+        let norm = self.norms[self.fieldnorms.fieldnorm_id(self.postings.doc()) as usize];
+        self.weight * tf / (tf + norm)
+    }
+}
+
+/// tantivy's `MoreLikeThisQuery`, which picks its terms from the searcher and
+/// so refuses to build without scoring. With scoring disabled this borrows
+/// the searcher anyway and scores every hit 1.
+#[derive(Clone, Debug)]
+pub(crate) struct SimilarQuery(pub(crate) MoreLikeThisQuery);
+
+impl Query for SimilarQuery {
+    fn weight(&self, enable_scoring: EnableScoring<'_>) -> tantivy::Result<Box<dyn Weight>> {
+        match enable_scoring {
+            EnableScoring::Enabled { .. } => self.0.weight(enable_scoring),
+            EnableScoring::Disabled { searcher_opt: Some(searcher), .. } => {
+                ConstScoreQuery::new(Box::new(self.0.clone()), 1.0).weight(EnableScoring::enabled_from_searcher(searcher))
+            }
+            EnableScoring::Disabled { searcher_opt: None, .. } => {
+                Err(TantivyError::InvalidArgument("more_like_this needs a searcher".to_string()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::segmented;
+    use tantivy::collector::TopDocs;
+    use tantivy::schema::{Field, Schema, Value, STORED, STRING, TEXT};
+    use tantivy::{doc, Searcher, TantivyDocument};
+
+    /// An in-RAM index with one segment per slice of texts. Each text is
+    /// stored as the document's `id` and indexed as its `body`.
+    fn index(segments: &[&[&str]]) -> (Searcher, Field, Field) {
+        let mut builder = Schema::builder();
+        let id = builder.add_text_field("id", STRING | STORED);
+        let body = builder.add_text_field("body", TEXT);
+        let docs = segments.iter().map(|texts| texts.iter().map(|t| doc!(id => *t, body => *t)).collect()).collect();
+        (segmented(builder.build(), docs), id, body)
+    }
+
+    /// Ids of the hits of `q`, best first.
+    fn hits(searcher: &Searcher, id: Field, q: &dyn Query) -> Vec<String> {
+        searcher.search(q, &TopDocs::with_limit(100)).unwrap().into_iter()
+            .map(|(_, addr)| {
+                let doc: TantivyDocument = searcher.doc(addr).unwrap();
+                doc.get_first(id).and_then(|v| v.as_str()).unwrap().to_string()
+            })
+            .collect()
+    }
+
+    /// Ids of the hits of `q`, sorted.
+    fn matches(searcher: &Searcher, id: Field, q: &dyn Query) -> Vec<String> {
+        let mut ids = hits(searcher, id, q);
+        ids.sort();
+        ids
+    }
+
+    fn term(field: Field, text: &str) -> Term { Term::from_field_text(field, text) }
+
+    fn term_query(field: Field, text: &str) -> Box<dyn Query> {
+        Box::new(TermQuery::new(term(field, text), IndexRecordOption::WithFreqs))
+    }
+
+    fn min_should_match(field: Field, texts: &[&str], min: usize) -> MinShouldMatchQuery {
+        MinShouldMatchQuery::new(texts.iter().map(|t| term_query(field, t)).collect(), min)
+    }
+
+    const LETTERS: [&[&str]; 2] = [&["a b c", "a b", "a", "b c d"], &["c d", "a b c d", "d"]];
+
+    #[test]
+    fn min_should_match_threshold() {
+        let (searcher, id, body) = index(&LETTERS);
+        let all = ["a", "b", "c", "d"];
+        assert_eq!(matches(&searcher, id, &min_should_match(body, &all, 2)), ["a b", "a b c", "a b c d", "b c d", "c d"]);
+        assert_eq!(matches(&searcher, id, &min_should_match(body, &all, 3)), ["a b c", "a b c d", "b c d"]);
+        assert_eq!(matches(&searcher, id, &min_should_match(body, &all, 4)), ["a b c d"]);
+        assert!(matches(&searcher, id, &min_should_match(body, &all, 5)).is_empty());
+    }
+
+    #[test]
+    fn min_should_match_seek_and_advance() {
+        let (searcher, id, body) = index(&LETTERS);
+        // Intersecting with a required term drives the scorer through seek.
+        let q = BooleanQuery::new(vec![
+            (Occur::Must, term_query(body, "d")),
+            (Occur::Must, Box::new(min_should_match(body, &["a", "b", "c"], 2))),
+        ]);
+        assert_eq!(matches(&searcher, id, &q), ["a b c d", "b c d"]);
+
+        let weight = min_should_match(body, &["a", "b", "c"], 2).weight(EnableScoring::enabled_from_searcher(&searcher)).unwrap();
+        for reader in searcher.segment_readers() {
+            let mut scorer = weight.scorer(reader, 1.0).unwrap();
+            let mut docs = Vec::new();
+            while scorer.doc() != TERMINATED {
+                docs.push(scorer.doc());
+                scorer.advance();
+            }
+            let expected: &[DocId] = if reader.num_docs() == 4 { &[0, 1, 3] } else { &[1] };
+            assert_eq!(docs, expected);
+
+            let mut scorer = weight.scorer(reader, 1.0).unwrap();
+            assert_eq!(scorer.seek(2), expected.iter().copied().find(|&d| d >= 2).unwrap_or(TERMINATED));
+            // Seeking backwards stays put.
+            let at = scorer.doc();
+            assert_eq!(scorer.seek(0), at);
+        }
+    }
+
+    fn proximity(field: Field, texts: &[&str], distance: u32, ordered: bool) -> ProximityQuery {
+        ProximityQuery::new(texts.iter().map(|t| term(field, t)).collect(), distance, ordered, None)
+    }
+
+    const CONTRACTS: [&[&str]; 2] = [
+        &["breach of contract", "contract was in breach"],
+        &["breach of the implied terms of the contract", "contract terms"],
+    ];
+
+    #[test]
+    fn proximity_unordered() {
+        let (searcher, id, body) = index(&CONTRACTS);
+        assert_eq!(matches(&searcher, id, &proximity(body, &["breach", "contract"], 2, false)), ["breach of contract", "contract was in breach"]);
+        assert_eq!(matches(&searcher, id, &proximity(body, &["contract", "breach"], 2, false)), ["breach of contract", "contract was in breach"]);
+        assert_eq!(matches(&searcher, id, &proximity(body, &["breach", "contract"], 1, false)), ["breach of contract"]);
+        assert_eq!(matches(&searcher, id, &proximity(body, &["breach", "contract"], 6, false)).len(), 3);
+    }
+
+    #[test]
+    fn proximity_ordered_rejects_reversed() {
+        let (searcher, id, body) = index(&CONTRACTS);
+        assert_eq!(matches(&searcher, id, &proximity(body, &["breach", "contract"], 2, true)), ["breach of contract"]);
+        assert_eq!(matches(&searcher, id, &proximity(body, &["contract", "breach"], 2, true)), ["contract was in breach"]);
+        assert_eq!(
+            matches(&searcher, id, &proximity(body, &["breach", "contract"], 6, true)),
+            ["breach of contract", "breach of the implied terms of the contract"],
+        );
+        assert!(matches(&searcher, id, &proximity(body, &["terms", "implied"], 6, true)).is_empty());
+    }
+
+    const FRUIT: [&[&str]; 2] = [&["apple", "pear"], &["apple apple apple x x x x x x x x x x x"]];
+
+    #[test]
+    fn bm25_defaults_match_term_query() {
+        let (searcher, _, body) = index(&FRUIT);
+        let scores = |q: &dyn Query| -> Vec<Score> {
+            searcher.search(q, &TopDocs::with_limit(10)).unwrap().into_iter().map(|(s, _)| s).collect()
+        };
+        let ours = scores(&Bm25TermQuery::new(term(body, "apple"), IndexRecordOption::WithFreqs, 1.2, 0.75));
+        let tantivy = scores(&*term_query(body, "apple"));
+        assert_eq!(ours.len(), 2);
+        for (a, b) in ours.iter().zip(&tantivy) { assert!((a - b).abs() < 1e-5, "{a} != {b}"); }
+    }
+
+    #[test]
+    fn bm25_override_changes_ranking() {
+        let (searcher, id, body) = index(&FRUIT);
+        let long = "apple apple apple x x x x x x x x x x x";
+        let bm25 = |k1, b| Bm25TermQuery::new(term(body, "apple"), IndexRecordOption::WithFreqs, k1, b);
+        // Full length normalization favours the short document...
+        assert_eq!(hits(&searcher, id, &bm25(1.2, 0.75)), ["apple", long]);
+        // ...none lets the long one's term frequency win.
+        assert_eq!(hits(&searcher, id, &bm25(1.2, 0.0)), [long, "apple"]);
+        // A higher k1 rewards term frequency even with some normalization.
+        assert_eq!(hits(&searcher, id, &bm25(10.0, 0.3)), [long, "apple"]);
+    }
+}
@@ -0,0 +1,124 @@
+//! Generation-tagged handle registry for objects exposed over FFI.
+//!
+//! A handle is a `u64`: the low 32 bits are the slot index + 1, the high 32 bits
+//! the slot's generation. 0 is never valid. Removing an entry bumps the slot's
+//! generation, so stale handles (double free, use after free) resolve to `None`
+//! instead of dangling. A slot whose generation would wrap is retired for good
+//! rather than reused, so an old handle can never match it again.
+
+use std::sync::Arc;
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<Arc<T>>,
+}
+
+pub(crate) struct Registry<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<usize>,
+}
+
+impl<T> Registry<T> {
+    pub(crate) const fn new() -> Self {
+        Registry { slots: Vec::new(), free: Vec::new() }
+    }
+
+    pub(crate) fn insert(&mut self, value: T) -> u64 {
+        let value = Some(Arc::new(value));
+        let idx = match self.free.pop() {
+            Some(i) => { self.slots[i].value = value; i }
+            None => {
+                self.slots.push(Slot { generation: 1, value });
+                self.slots.len() - 1
+            }
+        };
+        encode(idx, self.slots[idx].generation)
+    }
+
+    /// Resolve a handle. The returned `Arc` keeps the object alive even if the
+    /// handle is removed concurrently.
+    pub(crate) fn get(&self, handle: u64) -> Option<Arc<T>> {
+        let (idx, generation) = decode(handle)?;
+        let slot = self.slots.get(idx)?;
+        if slot.generation != generation { return None; }
+        slot.value.clone()
+    }
+
+    pub(crate) fn remove(&mut self, handle: u64) -> Option<Arc<T>> {
+        let (idx, generation) = decode(handle)?;
+        let slot = self.slots.get_mut(idx)?;
+        if slot.generation != generation { return None; }
+        let value = slot.value.take()?;
+        if let Some(next) = slot.generation.checked_add(1) {
+            slot.generation = next;
+            self.free.push(idx);
+        }
+        Some(value)
+    }
+}
+
+fn encode(idx: usize, generation: u32) -> u64 {
+    ((generation as u64) << 32) | (idx as u64 + 1)
+}
+
+fn decode(handle: u64) -> Option<(usize, u32)> {
+    let low = (handle & 0xFFFF_FFFF) as usize;
+    if low == 0 { return None; }
+    Some((low - 1, (handle >> 32) as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_handle_is_invalid() {
+        let mut r = Registry::new();
+        r.insert("a");
+        assert!(r.get(0).is_none());
+        assert!(r.remove(0).is_none());
+        // Slot bits of 0 with a non-zero generation are just as invalid.
+        assert!(r.get(1 << 32).is_none());
+    }
+
+    #[test]
+    fn stale_handle_after_remove() {
+        let mut r = Registry::new();
+        let h = r.insert("a");
+        assert_eq!(r.get(h).as_deref(), Some(&"a"));
+        assert_eq!(r.remove(h).as_deref(), Some(&"a"));
+        assert!(r.get(h).is_none());
+        assert!(r.remove(h).is_none(), "double remove");
+    }
+
+    #[test]
+    fn reused_slot_gets_a_new_handle() {
+        let mut r = Registry::new();
+        let a = r.insert("a");
+        r.remove(a);
+        let b = r.insert("b");
+        assert_ne!(a, b);
+        assert_eq!(decode(a).unwrap().0, decode(b).unwrap().0, "slot reused");
+        assert!(r.get(a).is_none());
+        assert!(r.remove(a).is_none());
+        assert_eq!(r.get(b).as_deref(), Some(&"b"));
+    }
+
+    #[test]
+    fn exhausted_slot_is_retired() {
+        let mut r = Registry::new();
+        let old = r.insert("old");
+        r.remove(old);
+        r.slots[0].generation = u32::MAX;
+        r.slots[0].value = Some(Arc::new("last"));
+        r.free.clear();
+        let last = encode(0, u32::MAX);
+        assert_eq!(r.remove(last).as_deref(), Some(&"last"));
+        // The slot is not handed out again, so no earlier generation can match it.
+        let next = r.insert("next");
+        assert_eq!(decode(next).unwrap().0, 1);
+        assert!(r.get(last).is_none());
+        assert!(r.get(old).is_none());
+        assert!(r.remove(last).is_none());
+    }
+}
@@ -0,0 +1,68 @@
+//! Fixtures shared by the unit tests.
+
+use std::ops::Deref;
+use tantivy::indexer::NoMergePolicy;
+use tantivy::schema::Schema;
+use tantivy::{Index, Searcher, TantivyDocument};
+use tempfile::TempDir;
+
+use crate::TantivyIndex;
+
+/// An in-RAM index with one segment per slice of documents, never merged.
+pub(crate) fn segmented(schema: Schema, segments: Vec<Vec<TantivyDocument>>) -> Searcher {
+    let index = Index::create_in_ram(schema);
+    let mut writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+    writer.set_merge_policy(Box::new(NoMergePolicy));
+    let n = segments.len();
+    for docs in segments {
+        for doc in docs { writer.add_document(doc).unwrap(); }
+        writer.commit().unwrap();
+    }
+    let searcher = index.reader().unwrap().searcher();
+    assert_eq!(searcher.segment_readers().len(), n);
+    searcher
+}
+
+/// A `TantivyIndex` in its own temporary directory, removed on drop.
+pub(crate) struct TempIndex {
+    // Declared first so it is dropped, releasing the writer lock, before the directory goes.
+    index: TantivyIndex,
+    _dir: TempDir,
+}
+
+impl TempIndex {
+    pub(crate) fn new(schema_json: &str) -> Self { Self::with_options(schema_json, "") }
+
+    pub(crate) fn with_options(schema_json: &str, options_json: &str) -> Self {
+        let dir = TempDir::new().unwrap();
+        let index = TantivyIndex::create_with_options(path(&dir), schema_json, options_json).unwrap();
+        TempIndex { index, _dir: dir }
+    }
+
+    pub(crate) fn path(&self) -> &str { path(&self._dir) }
+
+    /// Close the index and open it again from the same directory.
+    pub(crate) fn reopen(self, options_json: &str) -> Self {
+        let TempIndex { index, _dir: dir } = self;
+        drop(index);
+        let index = TantivyIndex::open_with_options(path(&dir), options_json).unwrap();
+        TempIndex { index, _dir: dir }
+    }
+
+    /// Index `docs`, then commit and refresh.
+    pub(crate) fn with_docs(schema_json: &str, docs: &[serde_json::Value]) -> Self {
+        let idx = Self::new(schema_json);
+        for doc in docs { idx.add_doc(&doc.to_string()).unwrap(); }
+        idx.commit().unwrap();
+        idx.refresh().unwrap();
+        idx
+    }
+
+}
+
+impl Deref for TempIndex {
+    type Target = TantivyIndex;
+    fn deref(&self) -> &TantivyIndex { &self.index }
+}
+
+fn path(dir: &TempDir) -> &str { dir.path().to_str().unwrap() }
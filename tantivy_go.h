@@ -1,27 +1,93 @@
 #ifndef TANTIVY_GO_H
 #define TANTIVY_GO_H
 
+#include <stddef.h>
 #include <stdint.h>
 
 #ifdef __cplusplus
 extern "C" {
 #endif
 
-typedef void* TantivyIndexHandle;
+// On failure *err is set to error JSON (free with tantivy_free_string):
+//   {"code":N,"kind":"bad_request|not_found|schema_mismatch|io|lock_busy|internal|panic",
+//    "message":"...","path":"..."}
+// Codes: 1 bad_request, 2 not_found, 3 schema_mismatch, 4 io, 5 lock_busy, 6 internal, 7 panic.
+//
+// Panics are caught at the boundary and reported with kind "panic" (code 7); the
+// function returns -2 (integer returns) or NULL / 0 (pointer / handle returns).
+
+// Handles are generation-tagged ids, not pointers. 0 is never valid; freed or
+// stale handles are rejected with an error instead of being dereferenced.
+typedef uint64_t TantivyIndexHandle;
+typedef uint64_t TantivySnapshotHandle;
+
+// Owned result buffer from the *_buf functions. data is NULL on error.
+typedef struct {
+    uint8_t* data;
+    size_t len;
+    size_t cap;
+} TantivyBuf;
 
 void tantivy_free_string(char* s);
+void tantivy_free_buf(TantivyBuf buf);
+// Fails pending tantivy_wait_for_opstamp calls with "index closed", waits for
+// other calls still running on the index to return, then releases it (including
+// the writer lock), so the path can be reopened right away.
 void tantivy_free_index(TantivyIndexHandle idx);
 
 TantivyIndexHandle tantivy_create_index(const char* path, const char* schema_json, char** err);
 TantivyIndexHandle tantivy_open_index(const char* path, char** err);
 
-int32_t tantivy_add_doc(TantivyIndexHandle idx, const char* doc_json, char** err);
-int32_t tantivy_commit(TantivyIndexHandle idx, char** err);
+// options_json: {"reload_policy":"on_commit"|"manual","auto_commit":{"max_docs":N,"interval_ms":T}};
+// NULL for defaults.
+TantivyIndexHandle tantivy_create_index_with_options(const char* path, const char* schema_json, const char* options_json, char** err);
+TantivyIndexHandle tantivy_open_index_with_options(const char* path, const char* options_json, char** err);
+
+// Write operations return the tantivy opstamp (>= 0) on success, -1 on error, -2 on panic.
+int64_t tantivy_add_doc(TantivyIndexHandle idx, const char* doc_json, char** err);
+int64_t tantivy_add_doc_buf(TantivyIndexHandle idx, const uint8_t* doc_json, size_t doc_len, char** err);
+int64_t tantivy_delete_term(TantivyIndexHandle idx, const char* field, const char* value_json, char** err);
+int64_t tantivy_delete_term_buf(TantivyIndexHandle idx, const uint8_t* field, size_t field_len, const uint8_t* value_json, size_t value_len, char** err);
+int64_t tantivy_commit(TantivyIndexHandle idx, char** err);
 uint64_t tantivy_num_docs(TantivyIndexHandle idx);
+int32_t tantivy_refresh(TantivyIndexHandle idx, char** err);
+
+// Background commits: {"max_docs":N,"interval_ms":T}; all zero disables.
+int32_t tantivy_set_auto_commit(TantivyIndexHandle idx, const char* config_json, char** err);
+// Last background commit error as error JSON, or NULL if none.
+char* tantivy_last_error(TantivyIndexHandle idx);
+
+// Returns 1 once searches see opstamp, 0 on timeout, -1 on error.
+// Fails with "index closed" if the handle is freed while waiting.
+int32_t tantivy_wait_for_opstamp(TantivyIndexHandle idx, uint64_t opstamp, uint64_t timeout_ms, char** err);
 
-// query_json follows the Query DSL: {"type":"text","query":"batman","limit":100}
+// query_json is a search request: {"query":{"type":"text","query":"batman"},"limit":100}
+// The flat form {"type":"text","query":"batman","limit":100} is also accepted.
+// Results are JSON; tantivy_search_encoded takes a (ptr, len) query and picks
+// other encodings.
 char* tantivy_search(TantivyIndexHandle idx, const char* query_json, char** err);
 
+// Parse and build query_json without searching. Returns JSON
+// {"request":{...},"fields":[...],"debug":"...","warnings":[...]}, or NULL with
+// err set; err's "path" points at the offending node, e.g. "query.must[2].field".
+char* tantivy_validate_query(TantivyIndexHandle idx, const char* query_json, char** err);
+TantivyBuf tantivy_validate_query_buf(TantivyIndexHandle idx, const uint8_t* query_json, size_t query_len, char** err);
+
+// Snapshots pin a searcher generation for consistent paging across commits. A
+// snapshot may only be searched through the index it was acquired from.
+TantivySnapshotHandle tantivy_acquire_snapshot(TantivyIndexHandle idx, char** err);
+void tantivy_release_snapshot(TantivySnapshotHandle snap);
+uint64_t tantivy_snapshot_num_docs(TantivySnapshotHandle snap);
+char* tantivy_search_snapshot(TantivyIndexHandle idx, TantivySnapshotHandle snap, const char* query_json, char** err);
+
+// Result encodings for tantivy_search_encoded.
+#define TANTIVY_ENCODING_JSON 0
+#define TANTIVY_ENCODING_MSGPACK 1
+#define TANTIVY_ENCODING_MSGPACK_COLUMNAR 2
+
+// snap = 0 searches the live reader; otherwise the given snapshot.
+TantivyBuf tantivy_search_encoded(TantivyIndexHandle idx, TantivySnapshotHandle snap, const uint8_t* query_json, size_t query_len, uint32_t encoding, char** err);
+
 #ifdef __cplusplus
 }
 #endif